@@ -0,0 +1,326 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use amqprs::{
+    callbacks::ChannelCallback,
+    channel::{BasicPublishArguments, Channel, ConfirmSelectArguments},
+    Ack, BasicProperties, Cancel, CloseChannel, FieldTable, Nack, Return,
+};
+use media_events::EventError;
+use tokio::{sync::oneshot, time::timeout};
+use tracing::{instrument, warn};
+
+/// How long a publish waits for the broker to confirm it.
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Why a message couldn't be handed to the broker.
+#[derive(Debug)]
+pub enum PublishError {
+    /// The message couldn't be serialized.
+    Encode(EventError),
+    /// The supervisor is between connections.
+    NotConnected,
+    /// The publish frame couldn't be sent.
+    Channel(String),
+    /// The broker refused to take responsibility for the message.
+    Nacked,
+    /// No queue is bound for the routing key, so the message went nowhere.
+    Unroutable(String),
+    /// The channel closed, or the broker never answered.
+    Unconfirmed,
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublishError::Encode(e) => write!(f, "{}", e),
+            PublishError::NotConnected => write!(f, "not connected to rabbitmq"),
+            PublishError::Channel(e) => write!(f, "failed to publish: {}", e),
+            PublishError::Nacked => write!(f, "message was nacked by the broker"),
+            PublishError::Unroutable(e) => write!(f, "message was returned: {}", e),
+            PublishError::Unconfirmed => write!(f, "message was never confirmed"),
+        }
+    }
+}
+
+type Confirmation = oneshot::Sender<Result<(), PublishError>>;
+
+/// Publishes on one channel still waiting for the broker, by delivery tag.
+pub struct Pending {
+    /// Delivery tag the next publish will get, counted the same way as the broker.
+    next_tag: u64,
+    waiting: BTreeMap<u64, Confirmation>,
+    /// Tags that came back through `basic.return`, with the broker's reason.
+    /// The broker still acks them afterwards.
+    returned: HashMap<u64, String>,
+}
+
+impl Default for Pending {
+    fn default() -> Self {
+        Self {
+            next_tag: 1,
+            waiting: BTreeMap::new(),
+            returned: HashMap::new(),
+        }
+    }
+}
+
+impl Pending {
+    /// Takes the tag of the next publish, along with where its outcome arrives.
+    pub fn register(&mut self) -> (u64, oneshot::Receiver<Result<(), PublishError>>) {
+        let (tx, rx) = oneshot::channel();
+        let tag = self.next_tag;
+        self.next_tag += 1;
+        self.waiting.insert(tag, tx);
+        (tag, rx)
+    }
+
+    /// Drops `tag` without an outcome, for a publish that never left.
+    pub fn forget(&mut self, tag: u64) {
+        self.waiting.remove(&tag);
+    }
+
+    /// Notes that `tag` came back unroutable, so its ack is reported as a failure.
+    pub fn returned(&mut self, tag: u64, reason: String) {
+        self.returned.insert(tag, reason);
+    }
+
+    /// Resolves `tag`, or every tag up to it when `multiple` is set.
+    pub fn settle(&mut self, tag: u64, multiple: bool, acked: bool) {
+        let tags: Vec<u64> = if multiple {
+            self.waiting.range(..=tag).map(|(t, _)| *t).collect()
+        } else {
+            vec![tag]
+        };
+        for tag in tags {
+            let returned = self.returned.remove(&tag);
+            let Some(confirmation) = self.waiting.remove(&tag) else {
+                continue;
+            };
+            let result = match (acked, returned) {
+                (true, None) => Ok(()),
+                (true, Some(reason)) => Err(PublishError::Unroutable(reason)),
+                (false, _) => Err(PublishError::Nacked),
+            };
+            let _ = confirmation.send(result);
+        }
+    }
+
+    /// Fails everything still waiting.
+    pub fn abandon(&mut self) {
+        for (_, confirmation) in std::mem::take(&mut self.waiting) {
+            let _ = confirmation.send(Err(PublishError::Unconfirmed));
+        }
+        self.returned.clear();
+    }
+}
+
+/// A channel in confirm mode whose publishes resolve once the broker acks them.
+///
+/// Publishes are mandatory and persistent, and tagged with their delivery tag
+/// as `message_id` so a returned message can be matched to the publish it came from.
+#[derive(Clone)]
+pub struct ConfirmedChannel {
+    channel: Channel,
+    pending: Arc<Mutex<Pending>>,
+    /// Serialises publishes so tags are handed out in the order frames are sent.
+    publish_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl ConfirmedChannel {
+    pub async fn new(channel: Channel) -> Result<Self, amqprs::error::Error> {
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        channel
+            .register_callback(ConfirmCallback {
+                pending: pending.clone(),
+            })
+            .await?;
+        channel
+            .confirm_select(ConfirmSelectArguments::default())
+            .await?;
+        Ok(Self {
+            channel,
+            pending,
+            publish_lock: Arc::new(tokio::sync::Mutex::new(())),
+        })
+    }
+
+    /// The underlying channel, for the odd call that isn't a publish.
+    pub fn channel(&self) -> &Channel {
+        &self.channel
+    }
+
+    pub async fn publish(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        content: Vec<u8>,
+    ) -> Result<(), PublishError> {
+        self.publish_with_headers(exchange, routing_key, FieldTable::new(), content)
+            .await
+    }
+
+    /// Publishes with extra `headers`, which the trace context is added to.
+    #[instrument(
+        name = "publish",
+        skip(self, headers, content),
+        fields(
+            otel.kind = "producer",
+            messaging.system = "rabbitmq",
+            messaging.destination.name = exchange,
+            messaging.rabbitmq.destination.routing_key = routing_key,
+        )
+    )]
+    pub async fn publish_with_headers(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        mut headers: FieldTable,
+        content: Vec<u8>,
+    ) -> Result<(), PublishError> {
+        let confirmed = {
+            let _guard = self.publish_lock.lock().await;
+            let (tag, rx) = self.pending.lock().unwrap().register();
+            // consumers continue the trace from this span
+            media_telemetry::inject(&mut headers);
+            let properties = BasicProperties::default()
+                .with_headers(headers)
+                .with_persistence(true)
+                .with_message_id(&tag.to_string())
+                .finish();
+            let args = BasicPublishArguments::new(exchange, routing_key)
+                .mandatory(true)
+                .finish();
+            if let Err(e) = self.channel.basic_publish(properties, content, args).await {
+                self.pending.lock().unwrap().forget(tag);
+                return Err(PublishError::Channel(e.to_string()));
+            }
+            rx
+        };
+        match timeout(CONFIRM_TIMEOUT, confirmed).await {
+            Ok(Ok(result)) => result,
+            // dropped with the channel, or timed out
+            _ => Err(PublishError::Unconfirmed),
+        }
+    }
+
+    /// Fails every publish still waiting, used once the channel is gone.
+    pub fn abandon(&self) {
+        self.pending.lock().unwrap().abandon();
+    }
+}
+
+struct ConfirmCallback {
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl ChannelCallback for ConfirmCallback {
+    fn close<'life0, 'life1, 'async_trait>(
+        &'life0 mut self,
+        _channel: &'life1 Channel,
+        close: CloseChannel,
+    ) -> Pin<Box<dyn Future<Output = Result<(), amqprs::error::Error>> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            warn!("publish channel closed by server: {}", close);
+            Ok(())
+        })
+    }
+
+    fn cancel<'life0, 'life1, 'async_trait>(
+        &'life0 mut self,
+        _channel: &'life1 Channel,
+        _cancel: Cancel,
+    ) -> Pin<Box<dyn Future<Output = Result<(), amqprs::error::Error>> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move { Ok(()) })
+    }
+
+    fn flow<'life0, 'life1, 'async_trait>(
+        &'life0 mut self,
+        _channel: &'life1 Channel,
+        active: bool,
+    ) -> Pin<Box<dyn Future<Output = Result<bool, amqprs::error::Error>> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move { Ok(active) })
+    }
+
+    fn publish_ack<'life0, 'life1, 'async_trait>(
+        &'life0 mut self,
+        _channel: &'life1 Channel,
+        ack: Ack,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            self.pending
+                .lock()
+                .unwrap()
+                .settle(ack.delivery_tag(), ack.mutiple(), true);
+        })
+    }
+
+    fn publish_nack<'life0, 'life1, 'async_trait>(
+        &'life0 mut self,
+        _channel: &'life1 Channel,
+        nack: Nack,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            self.pending
+                .lock()
+                .unwrap()
+                .settle(nack.delivery_tag(), nack.multiple(), false);
+        })
+    }
+
+    fn publish_return<'life0, 'life1, 'async_trait>(
+        &'life0 mut self,
+        _channel: &'life1 Channel,
+        ret: Return,
+        basic_properties: BasicProperties,
+        _content: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            let tag = basic_properties
+                .message_id()
+                .and_then(|id| id.parse::<u64>().ok());
+            match tag {
+                Some(tag) => {
+                    self.pending.lock().unwrap().returned(tag, ret.to_string());
+                }
+                None => warn!("unmatched returned message: {}", ret),
+            }
+        })
+    }
+}
@@ -0,0 +1,12 @@
+//! RabbitMQ plumbing shared by the media services.
+//!
+//! A [`Supervisor`] keeps one connection open, redeclaring the service's
+//! topology on every reconnect, and publishes through a [`ConfirmedChannel`],
+//! so a publish only succeeds once the broker has taken responsibility for
+//! the message.
+
+mod confirm;
+mod supervisor;
+
+pub use confirm::{ConfirmedChannel, Pending, PublishError};
+pub use supervisor::{ConnectionState, RabbitmqConfig, Supervisor, Topology};
@@ -0,0 +1,163 @@
+use std::{future::Future, pin::Pin, time::Duration};
+
+use amqprs::{
+    channel::{Channel, ExchangeDeclareArguments},
+    connection::{Connection, OpenConnectionArguments},
+};
+use tokio::{
+    sync::{watch, RwLock},
+    time::{interval, sleep},
+};
+use tracing::{error, info, warn};
+
+use crate::ConfirmedChannel;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// How often the connection is checked for closures that aren't network failures,
+/// like the broker closing a channel.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Declares the queues, bindings and consumers that have to exist on every
+/// new connection. It's handed its own channel, which lives as long as the connection.
+pub type Topology =
+    Box<dyn Fn(Channel) -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> + Send + Sync>;
+
+/// Where to connect and the topic exchange every service publishes to.
+#[derive(Clone, Debug)]
+pub struct RabbitmqConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub password: String,
+    pub exchange: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+impl ConnectionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Disconnected => "disconnected",
+        }
+    }
+}
+
+/// Keeps a connection to RabbitMQ open, reconnecting with backoff and
+/// redeclaring the exchange and topology whenever it drops.
+pub struct Supervisor {
+    config: RabbitmqConfig,
+    channel: RwLock<Option<ConfirmedChannel>>,
+    state: watch::Sender<ConnectionState>,
+}
+
+impl Supervisor {
+    pub fn new(config: RabbitmqConfig) -> Self {
+        Self {
+            config,
+            channel: RwLock::new(None),
+            state: watch::Sender::new(ConnectionState::Connecting),
+        }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    /// The channel to publish on, or `None` while disconnected.
+    pub async fn channel(&self) -> Option<ConfirmedChannel> {
+        self.channel.read().await.clone()
+    }
+
+    pub async fn run(&self, topology: Topology) {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            self.state.send_replace(ConnectionState::Connecting);
+            match self.connect(&topology).await {
+                Ok((connection, channels)) => {
+                    backoff = MIN_BACKOFF;
+                    self.state.send_replace(ConnectionState::Connected);
+                    info!("connected to rabbitmq");
+                    closed(&connection, &channels).await;
+                    if let Some(channel) = self.channel.write().await.take() {
+                        channel.abandon();
+                    }
+                    self.state.send_replace(ConnectionState::Disconnected);
+                    warn!("rabbitmq connection lost, reconnecting");
+                }
+                Err(e) => {
+                    self.state.send_replace(ConnectionState::Disconnected);
+                    error!(
+                        "failed to connect to rabbitmq, retrying in {:?}: {}",
+                        backoff, e
+                    );
+                }
+            }
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Opens a connection with a publishing channel in confirm mode and a
+    /// channel for the topology's consumers. Both channels are returned so they aren't closed on drop.
+    async fn connect(&self, topology: &Topology) -> Result<(Connection, [Channel; 2]), String> {
+        let connection = Connection::open(&OpenConnectionArguments::new(
+            &self.config.host,
+            self.config.port,
+            &self.config.username,
+            &self.config.password,
+        ))
+        .await
+        .map_err(|e| e.to_string())?;
+        let channel = connection
+            .open_channel(None)
+            .await
+            .map_err(|e| e.to_string())?;
+        channel
+            .exchange_declare(
+                ExchangeDeclareArguments::default()
+                    .exchange(self.config.exchange.clone())
+                    .exchange_type("topic".to_owned())
+                    .finish(),
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        let consumer_channel = connection
+            .open_channel(None)
+            .await
+            .map_err(|e| e.to_string())?;
+        topology(consumer_channel.clone()).await?;
+        let confirmed = ConfirmedChannel::new(channel.clone())
+            .await
+            .map_err(|e| e.to_string())?;
+        *self.channel.write().await = Some(confirmed);
+        Ok((connection, [channel, consumer_channel]))
+    }
+}
+
+/// Resolves once the connection or any of its channels is gone.
+async fn closed(connection: &Connection, channels: &[Channel]) {
+    let mut check = interval(CHECK_INTERVAL);
+    tokio::select! {
+        _ = connection.listen_network_io_failure() => {}
+        _ = async {
+            loop {
+                check.tick().await;
+                if !connection.is_open() || channels.iter().any(|c| !c.is_open()) {
+                    break;
+                }
+            }
+        } => {}
+    }
+}
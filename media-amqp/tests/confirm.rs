@@ -0,0 +1,97 @@
+//! Pins how broker acks, nacks and returns resolve waiting publishes. The
+//! broker numbers deliveries per channel and may settle several at once, so
+//! every tag has to end up with exactly one outcome.
+
+use media_amqp::{Pending, PublishError};
+use tokio::sync::oneshot::{error::TryRecvError, Receiver};
+
+type Outcome = Receiver<Result<(), PublishError>>;
+
+fn register(pending: &mut Pending, count: usize) -> Vec<(u64, Outcome)> {
+    (0..count).map(|_| pending.register()).collect()
+}
+
+#[test]
+fn tags_count_from_one() {
+    let mut pending = Pending::default();
+    let tags: Vec<u64> = register(&mut pending, 3)
+        .into_iter()
+        .map(|(t, _)| t)
+        .collect();
+    assert_eq!(tags, vec![1, 2, 3]);
+}
+
+#[test]
+fn single_ack_resolves_only_its_tag() {
+    let mut pending = Pending::default();
+    let mut outcomes = register(&mut pending, 2);
+    pending.settle(2, false, true);
+    assert!(matches!(outcomes[1].1.try_recv(), Ok(Ok(()))));
+    assert!(matches!(outcomes[0].1.try_recv(), Err(TryRecvError::Empty)));
+}
+
+#[test]
+fn multiple_ack_resolves_every_tag_up_to_it() {
+    let mut pending = Pending::default();
+    let mut outcomes = register(&mut pending, 3);
+    pending.settle(2, true, true);
+    assert!(matches!(outcomes[0].1.try_recv(), Ok(Ok(()))));
+    assert!(matches!(outcomes[1].1.try_recv(), Ok(Ok(()))));
+    assert!(matches!(outcomes[2].1.try_recv(), Err(TryRecvError::Empty)));
+    pending.settle(3, true, true);
+    assert!(matches!(outcomes[2].1.try_recv(), Ok(Ok(()))));
+}
+
+#[test]
+fn nack_fails_the_publish() {
+    let mut pending = Pending::default();
+    let mut outcomes = register(&mut pending, 2);
+    pending.settle(2, true, false);
+    assert!(matches!(
+        outcomes[0].1.try_recv(),
+        Ok(Err(PublishError::Nacked))
+    ));
+    assert!(matches!(
+        outcomes[1].1.try_recv(),
+        Ok(Err(PublishError::Nacked))
+    ));
+}
+
+#[test]
+fn returned_then_acked_is_unroutable() {
+    let mut pending = Pending::default();
+    let mut outcomes = register(&mut pending, 2);
+    pending.returned(1, "NO_ROUTE".to_string());
+    pending.settle(2, true, true);
+    match outcomes[0].1.try_recv() {
+        Ok(Err(PublishError::Unroutable(reason))) => assert_eq!(reason, "NO_ROUTE"),
+        other => panic!("expected unroutable, got {:?}", other),
+    }
+    assert!(matches!(outcomes[1].1.try_recv(), Ok(Ok(()))));
+}
+
+#[test]
+fn settling_unknown_or_settled_tags_is_ignored() {
+    let mut pending = Pending::default();
+    let mut outcomes = register(&mut pending, 1);
+    pending.settle(1, false, true);
+    pending.settle(1, false, false);
+    pending.settle(7, true, true);
+    assert!(matches!(outcomes[0].1.try_recv(), Ok(Ok(()))));
+}
+
+#[test]
+fn forgotten_and_abandoned_publishes_are_unconfirmed() {
+    let mut pending = Pending::default();
+    let mut outcomes = register(&mut pending, 2);
+    pending.forget(1);
+    assert!(matches!(
+        outcomes[0].1.try_recv(),
+        Err(TryRecvError::Closed)
+    ));
+    pending.abandon();
+    assert!(matches!(
+        outcomes[1].1.try_recv(),
+        Ok(Err(PublishError::Unconfirmed))
+    ));
+}
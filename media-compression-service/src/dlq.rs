@@ -0,0 +1,81 @@
+use amqprs::channel::{BasicAckArguments, BasicGetArguments, BasicNackArguments};
+use media_amqp::ConfirmedChannel;
+
+use crate::rabbitmq_client::retry::{self, DEAD_LETTER_HEADERS, DEAD_LETTER_QUEUE};
+
+/// Admin commands for the dead letter queue, run as
+/// `media-compression-service dlq list [count]` or `dlq replay [count]`.
+pub async fn run(channel: &ConfirmedChannel, args: &[String]) -> Result<(), String> {
+    let count = match args.get(1) {
+        Some(count) => count
+            .parse::<usize>()
+            .map_err(|_| format!("invalid count: {}", count))?,
+        None => usize::MAX,
+    };
+    match args.first().map(String::as_str) {
+        Some("list") => list(channel, count).await,
+        Some("replay") => replay(channel, count).await,
+        _ => Err("usage: dlq <list|replay> [count]".to_owned()),
+    }
+}
+
+/// Prints up to `count` dead-lettered uploads and leaves them in the queue.
+async fn list(channel: &ConfirmedChannel, count: usize) -> Result<(), String> {
+    let channel = channel.channel();
+    let mut last_tag = None;
+    for _ in 0..count {
+        let Some((deliver, properties, content)) = channel
+            .basic_get(BasicGetArguments::new(DEAD_LETTER_QUEUE))
+            .await
+            .map_err(|e| e.to_string())?
+        else {
+            break;
+        };
+        let headers = DEAD_LETTER_HEADERS
+            .iter()
+            .map(|name| {
+                format!(
+                    "{}={}",
+                    name,
+                    retry::header(&properties, name).unwrap_or_default()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!("{} {}", headers, String::from_utf8_lossy(&content));
+        last_tag = Some(deliver.delivery_tag());
+    }
+    // hand everything back untouched
+    if let Some(tag) = last_tag {
+        channel
+            .basic_nack(BasicNackArguments::new(tag, true, true))
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Moves up to `count` dead-lettered uploads back onto the compression queue.
+async fn replay(channel: &ConfirmedChannel, count: usize) -> Result<(), String> {
+    let mut replayed = 0;
+    while replayed < count {
+        let Some((deliver, _, content)) = channel
+            .channel()
+            .basic_get(BasicGetArguments::new(DEAD_LETTER_QUEUE))
+            .await
+            .map_err(|e| e.to_string())?
+        else {
+            break;
+        };
+        // only dropped from the dead letter queue once the replay is confirmed
+        retry::replay(channel, content).await?;
+        channel
+            .channel()
+            .basic_ack(BasicAckArguments::new(deliver.delivery_tag(), false))
+            .await
+            .map_err(|e| e.to_string())?;
+        replayed += 1;
+    }
+    println!("replayed {} messages", replayed);
+    Ok(())
+}
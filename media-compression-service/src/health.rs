@@ -0,0 +1,36 @@
+use std::{env, path::PathBuf};
+
+use media_amqp::ConnectionState;
+use tokio::{fs, sync::watch};
+use tracing::error;
+
+const DEFAULT_HEALTH_FILE: &str = "/tmp/media-compression-service.healthy";
+
+/// Path of the file that exists while the service is healthy, from `healthFile`.
+pub fn health_file() -> PathBuf {
+    env::var("healthFile")
+        .unwrap_or_else(|_| DEFAULT_HEALTH_FILE.to_owned())
+        .into()
+}
+
+/// The service has no HTTP server to probe, so the RabbitMQ connection state
+/// is mirrored into a file that only exists while connected.
+pub async fn report_connection(path: PathBuf, mut state: watch::Receiver<ConnectionState>) {
+    loop {
+        let connected = *state.borrow_and_update() == ConnectionState::Connected;
+        let result = if connected {
+            fs::write(&path, ConnectionState::Connected.as_str()).await
+        } else {
+            match fs::remove_file(&path).await {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            }
+        };
+        if let Err(e) = result {
+            error!("Failed to update health file {}: {}", path.display(), e);
+        }
+        if state.changed().await.is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use uuid::Uuid;
+
+/// Ids of uploads this process has already compressed, so redeliveries of
+/// their event are acked without touching S3. A bounded in-memory stand-in
+/// for a shared store: it starts empty after a restart, where the manifest
+/// written next to the renditions keeps the work from being redone.
+#[derive(Clone)]
+pub struct Ledger {
+    capacity: usize,
+    entries: Arc<Mutex<Entries>>,
+}
+
+#[derive(Default)]
+struct Entries {
+    ids: HashSet<Uuid>,
+    /// Insertion order, to evict the oldest id once full.
+    order: VecDeque<Uuid>,
+}
+
+impl Ledger {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Arc::new(Mutex::new(Entries::default())),
+        }
+    }
+
+    /// Events from before envelopes have the nil id and are never recorded.
+    pub fn contains(&self, event_id: Uuid) -> bool {
+        self.entries.lock().unwrap().ids.contains(&event_id)
+    }
+
+    pub fn record(&self, event_id: Uuid) {
+        if event_id.is_nil() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.ids.insert(event_id) {
+            return;
+        }
+        entries.order.push_back(event_id);
+        if entries.order.len() > self.capacity {
+            if let Some(oldest) = entries.order.pop_front() {
+                entries.ids.remove(&oldest);
+            }
+        }
+    }
+}
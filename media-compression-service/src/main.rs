@@ -1,16 +1,41 @@
-use amqprs::channel::{BasicConsumeArguments, QueueBindArguments, QueueDeclareArguments};
+use amqprs::channel::{
+    BasicConsumeArguments, BasicQosArguments, Channel, QueueBindArguments, QueueDeclareArguments,
+};
 use aws_config::BehaviorVersion;
 use aws_sdk_s3::config::{Credentials, Region};
+mod dlq;
+mod health;
+mod ledger;
+mod metrics;
 mod rabbitmq_client;
-use log::LevelFilter;
-use rabbitmq_client::client::{CustomConsumer, RabbitmqClient, RabbitmqConfig};
-use simplelog::SimpleLogger;
-use std::env;
+mod rendition;
+mod worker;
+use ledger::Ledger;
+use media_amqp::RabbitmqConfig;
+use media_dispatch::DispatchMetrics;
+use rabbitmq_client::{
+    client::{RabbitmqClient, UploadHandler},
+    retry::{RetryPolicy, COMPRESSION_QUEUE},
+};
+use rendition::{config::CompressionConfig, resize};
+use std::{env, sync::Arc, time::Duration};
 use tokio::signal;
+use tracing::{error, info};
+use worker::WorkerPool;
+
+const METRICS_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+/// Compressed events remembered for skipping redeliveries.
+const LEDGER_CAPACITY: usize = 10_000;
 
 #[tokio::main]
 async fn main() {
-    SimpleLogger::init(LevelFilter::Debug, simplelog::Config::default()).unwrap();
+    let telemetry = match media_telemetry::init("media-compression-service") {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
 
     let key_id = env::var("minioID").unwrap();
     let secret_key = env::var("minioAccessKey").unwrap();
@@ -25,49 +50,125 @@ async fn main() {
         .build();
 
     let client = aws_sdk_s3::Client::from_conf(s3_config);
-    let rabbitmq_client = RabbitmqClient::new(
-        RabbitmqConfig {
-            host: "rabbitmq".to_owned(),
-            port: 5672,
-            username: "guest".to_owned(),
-            password: "guest".to_owned(),
-            exchange: "media_events".to_owned(),
-        },
-        client.clone(),
-    )
-    .await
-    .unwrap();
+    let config = match CompressionConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let workers = match WorkerPool::from_env() {
+        Ok(workers) => workers,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    let retry = match RetryPolicy::from_env() {
+        Ok(retry) => retry,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+    info!("{:?}, workers: {}, {:?}", config, workers.size(), retry);
+    resize::log_cpu_extensions();
+    tokio::spawn(resize::report_resize_timings(METRICS_REPORT_INTERVAL));
+    let rabbitmq_client = RabbitmqClient::new(RabbitmqConfig {
+        host: "rabbitmq".to_owned(),
+        port: 5672,
+        username: "guest".to_owned(),
+        password: "guest".to_owned(),
+        exchange: "media_events".to_owned(),
+    });
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("dlq") {
+        let dlq_retry = retry.clone();
+        rabbitmq_client.supervise(Box::new(move |channel| {
+            let retry = dlq_retry.clone();
+            Box::pin(async move { retry.declare(&channel).await })
+        }));
+        let channel = rabbitmq_client.connected_channel().await;
+        if let Err(e) = dlq::run(&channel, &args[1..]).await {
+            error!("{}", e);
+        }
+        return;
+    }
+
+    tokio::spawn(health::report_connection(
+        health::health_file(),
+        rabbitmq_client.subscribe(),
+    ));
+    let handler = UploadHandler {
+        s3_client: client.clone(),
+        config: Arc::new(config),
+        retry,
+        ledger: Ledger::new(LEDGER_CAPACITY),
+        rabbitmq_client: rabbitmq_client.clone(),
+    };
+    // shared across reconnects so the counts cover the whole process
+    let dispatch_metrics = Arc::new(DispatchMetrics::default());
+    tokio::spawn(metrics::report_dispatch(
+        dispatch_metrics.clone(),
+        METRICS_REPORT_INTERVAL,
+    ));
+    let exchange = rabbitmq_client.config.exchange.clone();
+    rabbitmq_client.supervise(Box::new(move |channel| {
+        Box::pin(declare_consumer(
+            channel,
+            exchange.clone(),
+            handler.clone(),
+            workers.clone(),
+            dispatch_metrics.clone(),
+        ))
+    }));
+    info!("awaiting shutdown signal");
+    shutdown_signal().await;
+    info!("shutdown signal received");
+    telemetry.shutdown();
+}
 
-    println!("consuming started");
-    let channel = rabbitmq_client.connection.open_channel(None).await.unwrap();
+/// Declares the retry and compression queues and starts consuming uploads on
+/// `channel`. Runs again on every reconnect.
+async fn declare_consumer(
+    channel: Channel,
+    exchange: String,
+    handler: UploadHandler,
+    workers: WorkerPool,
+    metrics: Arc<DispatchMetrics>,
+) -> Result<(), String> {
+    handler.retry.declare(&channel).await?;
+    let dispatcher = handler.dispatcher(metrics, &workers);
     let (queue_name, _, _) = channel
         .queue_declare(
             QueueDeclareArguments::default()
-                .queue("media_compresion_service".to_owned())
+                .queue(COMPRESSION_QUEUE.to_owned())
                 .durable(true)
                 .finish(),
         )
         .await
-        .unwrap()
-        .unwrap();
+        .map_err(|e| format!("Error declaring queue: {}", e))?
+        .ok_or_else(|| "Queue declaration returned nothing".to_owned())?;
+    for routing_key in dispatcher.routing_keys() {
+        channel
+            .queue_bind(QueueBindArguments::new(&queue_name, &exchange, routing_key))
+            .await
+            .map_err(|e| format!("Error binding queue: {}", e))?;
+    }
+    // only take as many unacked uploads as there are workers to compress them
     channel
-        .queue_bind(QueueBindArguments::new(
-            &queue_name,
-            &rabbitmq_client.config.exchange,
-            "media.uploaded",
-        ))
+        .basic_qos(BasicQosArguments::new(0, workers.size() as u16, false))
         .await
-        .unwrap();
+        .map_err(|e| format!("Error setting prefetch: {}", e))?;
+    info!("consuming started");
     // start consumer with given name
     let args = BasicConsumeArguments::new(&queue_name, "example_basic_pub_sub");
-
-    let consumer = CustomConsumer {
-        s3_client: client.clone(),
-    };
-    channel.basic_consume(consumer, args).await.unwrap();
-    println!("awaitng shutdown signal");
-    shutdown_signal().await;
-    println!("shutdown signal received");
+    channel
+        .basic_consume(dispatcher, args)
+        .await
+        .map_err(|e| format!("Error starting consumer: {}", e))?;
+    Ok(())
 }
 
 async fn shutdown_signal() {
@@ -0,0 +1,61 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use media_dispatch::DispatchMetrics;
+use tracing::info;
+
+/// Running count, total and maximum of a timed operation.
+pub struct DurationMetric {
+    name: &'static str,
+    count: AtomicU64,
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl DurationMetric {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            count: AtomicU64::new(0),
+            total_micros: AtomicU64::new(0),
+            max_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record(&self, elapsed: Duration) {
+        let micros = elapsed.as_micros() as u64;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    /// Logs count, mean and max, skipping metrics that were never recorded.
+    pub fn log(&self) {
+        let count = self.count.load(Ordering::Relaxed);
+        if count == 0 {
+            return;
+        }
+        let total = self.total_micros.load(Ordering::Relaxed);
+        let max = self.max_micros.load(Ordering::Relaxed);
+        info!(
+            "metric {}: count={} mean={:.2}ms max={:.2}ms",
+            self.name,
+            count,
+            total as f64 / count as f64 / 1000.0,
+            max as f64 / 1000.0
+        );
+    }
+}
+
+/// Logs how consumed deliveries were settled every `interval`.
+pub async fn report_dispatch(metrics: Arc<DispatchMetrics>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        info!("metric dispatch: {}", metrics);
+    }
+}
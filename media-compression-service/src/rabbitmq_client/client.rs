@@ -1,329 +1,641 @@
-use amqprs::{
-    channel::{BasicAckArguments, BasicPublishArguments, Channel, ExchangeDeclareArguments},
-    connection::{Connection, OpenConnectionArguments},
-    consumer::AsyncConsumer,
-    BasicProperties, Deliver,
-};
 use aws_sdk_s3::Client;
-use image::{imageops::FilterType, GenericImageView};
-use log::{error, info};
-use std::{
-    fmt::Debug,
-    time::{Duration, Instant},
+use image::{
+    codecs::avif::AvifEncoder, ExtendedColorType, GenericImageView, ImageBuffer, ImageEncoder,
+    ImageError, ImageReader, Limits, Pixel,
 };
-use tokio::time::sleep;
+use media_amqp::{ConfirmedChannel, ConnectionState, RabbitmqConfig, Supervisor, Topology};
+use media_dispatch::{Delivery, DispatchMetrics, Dispatcher, Outcome};
+use media_events::{Envelope, Event};
+use std::{borrow::Cow, fmt::Debug, io::Cursor, sync::Arc};
+use tokio::sync::watch;
+use tracing::{error, info, info_span, instrument};
 use turbojpeg::{PixelFormat, Subsamp};
 
-use crate::rabbitmq_client::models::{MediaCompressedMessage, MediaUploadedMessage};
+use crate::{
+    ledger::Ledger,
+    rabbitmq_client::models::{
+        MediaCompressedMessage, MediaCompressionFailedMessage, MediaUploadedMessage, Rendition,
+        PRODUCER,
+    },
+    rabbitmq_client::retry::{self, RetryPolicy},
+    rendition::{
+        config::CompressionConfig,
+        format::OutputFormat,
+        plan::{self, Plan},
+        probe::{self, ImageProbe},
+    },
+    worker::WorkerPool,
+};
 
 #[derive(Clone)]
 pub struct RabbitmqClient {
-    s3_client: aws_sdk_s3::Client,
-    pub channel: Channel,
-    pub connection: Connection,
+    supervisor: Arc<Supervisor>,
     pub config: RabbitmqConfig,
 }
 
-#[derive(Clone, Debug)]
-pub struct RabbitmqConfig {
-    pub host: String,
-    pub port: u16,
-    pub username: String,
-    pub password: String,
-    pub exchange: String,
-}
-
 impl RabbitmqClient {
-    pub async fn new(
-        config: RabbitmqConfig,
-        s3_client: aws_sdk_s3::Client,
-    ) -> Result<Self, String> {
-        let connection = Connection::open(&OpenConnectionArguments::new(
-            &config.host,
-            config.port,
-            &config.username,
-            &config.password,
-        ))
-        .await
-        .map_err(|e| format!("Error opening connection: {}", e))?;
+    /// Creates a client that stays disconnected until `supervise` is called.
+    pub fn new(config: RabbitmqConfig) -> Self {
+        Self {
+            supervisor: Arc::new(Supervisor::new(config.clone())),
+            config,
+        }
+    }
 
-        let channel = connection
-            .open_channel(None)
-            .await
-            .map_err(|e| format!("Error opening channel: {}", e))?;
-
-        channel
-            .exchange_declare(
-                ExchangeDeclareArguments::default()
-                    .exchange("media_events".to_owned())
-                    .exchange_type("topic".to_owned())
-                    .finish(),
-            )
-            .await
-            .map_err(|e| format!("Error declaring exchange: {}", e))?;
+    /// Connects in the background and keeps reconnecting whenever the
+    /// connection drops, declaring `topology` again each time.
+    pub fn supervise(&self, topology: Topology) {
+        let supervisor = self.supervisor.clone();
+        tokio::spawn(async move { supervisor.run(topology).await });
+    }
 
-        Ok(Self {
-            connection,
-            channel,
-            config,
-            s3_client,
-        })
+    pub fn subscribe(&self) -> watch::Receiver<ConnectionState> {
+        self.supervisor.subscribe()
     }
 
-    pub async fn send_message(&self, message: impl Into<Vec<u8>>) -> Result<(), String> {
-        let args = BasicPublishArguments::new(&self.config.exchange, "media.uploaded");
+    /// The channel to publish on, or `None` while disconnected.
+    pub async fn channel(&self) -> Option<ConfirmedChannel> {
+        self.supervisor.channel().await
+    }
 
-        self.channel
-            .basic_publish(BasicProperties::default(), message.into(), args)
-            .await
-            .map_err(|e| format!("Failed to publish message: {}", e))
+    /// Waits for the supervisor to connect and returns its publishing channel.
+    pub async fn connected_channel(&self) -> ConfirmedChannel {
+        let mut state = self.subscribe();
+        loop {
+            if let Some(channel) = self.supervisor.channel().await {
+                return channel;
+            }
+            let _ = state.changed().await;
+        }
     }
 }
 
-pub struct CustomConsumer {
+/// Compresses the uploads media-service announces.
+#[derive(Clone)]
+pub struct UploadHandler {
     pub s3_client: Client,
+    pub config: Arc<CompressionConfig>,
+    pub retry: RetryPolicy,
+    pub ledger: Ledger,
+    /// Publishes results, retries and dead letters with broker confirms.
+    pub rabbitmq_client: RabbitmqClient,
 }
 
-// impl AsyncConsumer for CustomConsumer {
-//     fn consume<'life0, 'life1, 'async_trait>(
-//         &'life0 mut self,
-//         channel: &'life1 Channel,
-//         deliver: Deliver,
-//         basic_properties: BasicProperties,
-//         content: Vec<u8>,
-//     ) -> ::core::pin::Pin<
-//         Box<dyn ::core::future::Future<Output = ()> + ::core::marker::Send + 'async_trait>,
-//     >
-//     where
-//         'life0: 'async_trait,
-//         'life1: 'async_trait,
-//         Self: 'async_trait,
-//     {
-//         Box::pin(async move {
-//             let data: MediaUploadedMessage = match serde_json::from_slice(&content) {
-//                 Ok(data) => data,
-//                 Err(e) => {
-//                     error!("Failed to parse message: {}", e);
-//                     return;
-//                 }
-//             };
-//             info!("Message received: {:?}", data);
-//             sleep(Duration::from_secs(2)).await;
-//             let start = Instant::now();
-//             match self
-//                 .s3_client
-//                 .get_object()
-//                 .bucket("media-service")
-//                 .key(&data.id)
-//                 .send()
-//                 .await
-//             {
-//                 Ok(img) => {
-//                     // info!("{:?}", img);
-//                     match img.content_length() {
-//                         Some(size) => {
-//                             if size < 8 * 1024 * 1024 {
-//                                 info!("image small enough no compression needed");
-//                                 if let Err(err) = channel
-//                                     .basic_ack(BasicAckArguments::new(
-//                                         deliver.delivery_tag(),
-//                                         false,
-//                                     ))
-//                                     .await
-//                                 {
-//                                     error!("Failed to acknowledge message: {}", err);
-//                                 }
-//                                 return;
-//                             }
-//                         }
-//                         None => return,
-//                     }
-//                     let img_bytes = match img.body.collect().await {
-//                         Ok(b) => b,
-//                         Err(e) => {
-//                             error!("Failed to collect image data: {}", e);
-//                             return;
-//                         }
-//                     };
-
-//                     let compressed_img = match compress_image(img_bytes.to_vec()) {
-//                         Ok(img) => img,
-//                         Err(e) => {
-//                             error!("Failed to compress image: {:?}", e);
-//                             return;
-//                         }
-//                     };
-
-//                     match self
-//                         .s3_client
-//                         .put_object()
-//                         .bucket("media-service")
-//                         .key(&data.compressed_id)
-//                         .body(compressed_img.into())
-//                         .content_type("application/jpeg")
-//                         .send()
-//                         .await
-//                     {
-//                         Ok(_) => info!("Image successfully compressed and uploaded"),
-//                         Err(e) => error!("Failed to upload compressed image: {}", e),
-//                     }
-//                 }
-//                 Err(e) => error!("Failed to retrieve image from S3: {}", e),
-//             }
-//             let end = Instant::now();
-//             info!("Image compression duration: {:?}", end - start);
-
-//             if let Err(err) = channel
-//                 .basic_ack(BasicAckArguments::new(deliver.delivery_tag(), false))
-//                 .await
-//             {
-//                 error!("Failed to acknowledge message: {}", err);
-//             }
-//         })
-//     }
-// }
-
-impl AsyncConsumer for CustomConsumer {
-    fn consume<'life0, 'life1, 'async_trait>(
-        &'life0 mut self,
-        channel: &'life1 Channel,
-        deliver: Deliver,
-        basic_properties: BasicProperties,
-        content: Vec<u8>,
-    ) -> ::core::pin::Pin<
-        Box<dyn ::core::future::Future<Output = ()> + ::core::marker::Send + 'async_trait>,
-    >
-    where
-        'life0: 'async_trait,
-        'life1: 'async_trait,
-        Self: 'async_trait,
-    {
-        let s3_client = self.s3_client.clone();
-        let channel = channel.clone();
-        Box::pin(async move {
-            // Spawn a new task to process the message concurrently
-            tokio::spawn(async move {
-                // Process the message
-                let data: MediaUploadedMessage = match serde_json::from_slice(&content) {
-                    Ok(data) => data,
-                    Err(e) => {
-                        error!("Failed to parse message: {}", e);
-                        return;
-                    }
-                };
-                info!("Message received: {:?}", data);
-                sleep(Duration::from_secs(2)).await;
-                match s3_client
-                    .get_object()
-                    .bucket("media-service")
-                    .key(&data.id)
-                    .send()
+impl UploadHandler {
+    /// Routes uploads to this handler, compressing as many at once as
+    /// `workers` has room for.
+    pub fn dispatcher(&self, metrics: Arc<DispatchMetrics>, workers: &WorkerPool) -> Dispatcher {
+        let handler = self.clone();
+        let rabbitmq_client = self.rabbitmq_client.clone();
+        Dispatcher::new(metrics)
+            .concurrent(workers.permits())
+            .on(move |envelope, delivery| handler.clone().handle(envelope, delivery))
+            .on_malformed(move |delivery, e| {
+                let rabbitmq_client = rabbitmq_client.clone();
+                async move {
+                    // nothing can fix the payload, so park it with the reason
+                    let message = format!("Failed to parse message: {}", e);
+                    error!("{}", message);
+                    let Some(channel) = rabbitmq_client.channel().await else {
+                        error!("Not connected to rabbitmq, requeueing malformed message");
+                        return Outcome::Requeue;
+                    };
+                    let attempt = retry::attempt(&delivery.properties);
+                    match retry::dead_letter(
+                        &channel,
+                        delivery.content,
+                        attempt,
+                        "invalid_message",
+                        &message,
+                    )
                     .await
-                {
-                    Ok(img) => {
-                        match img.content_length() {
-                            Some(size) => {
-                                if size < 8 * 1024 * 1024 {
-                                    info!("image small enough no compression needed");
-                                    if let Err(err) = channel
-                                        .basic_ack(BasicAckArguments::new(
-                                            deliver.delivery_tag(),
-                                            false,
-                                        ))
-                                        .await
-                                    {
-                                        error!("Failed to acknowledge message: {}", err);
-                                    }
-                                    return;
-                                }
-                            }
-                            None => return,
-                        }
-                        let img_bytes = match img.body.collect().await {
-                            Ok(b) => b,
-                            Err(e) => {
-                                error!("Failed to collect image data: {}", e);
-                                return;
-                            }
-                        };
-
-                        let compressed_img = match compress_image(img_bytes.to_vec()) {
-                            Ok(img) => img,
-                            Err(e) => {
-                                error!("Failed to compress image: {:?}", e);
-                                return;
-                            }
-                        };
-
-                        match s3_client
-                            .put_object()
-                            .bucket("media-service")
-                            .key(&data.compressed_id)
-                            .body(compressed_img.into())
-                            .send()
-                            .await
-                        {
-                            Ok(_) => {
-                                info!("Image successfully compressed and uploaded");
-                                let publish_result = channel
-                                    .basic_publish(
-                                        BasicProperties::default(),
-                                        MediaCompressedMessage {
-                                            id: data.id,
-                                            compressed_id: data.compressed_id,
-                                            status: "compressed".to_string(),
-                                        }
-                                        .into(),
-                                        BasicPublishArguments::default()
-                                            .exchange("media_events".to_string())
-                                            .routing_key("media.compressed".to_string())
-                                            .finish(),
-                                    )
-                                    .await;
-                                match publish_result {
-                                    Ok(sucess) => return,
-                                    Err(e) => error!("{}", e.to_string()),
-                                }
-                            }
-                            Err(e) => error!("Failed to upload compressed image: {}", e),
+                    {
+                        Ok(()) => Outcome::Ack,
+                        Err(e) => {
+                            error!("{}", e);
+                            Outcome::Requeue
                         }
                     }
-                    Err(e) => error!("Failed to retrieve image from S3: {}", e),
                 }
+            })
+    }
 
-                // Acknowledge the message once processed
-                if let Err(err) = channel
-                    .basic_ack(BasicAckArguments::new(deliver.delivery_tag(), false))
-                    .await
-                {
-                    error!("Failed to acknowledge message: {}", err);
-                }
-            });
-        })
+    async fn handle(self, envelope: Envelope<MediaUploadedMessage>, delivery: Delivery) -> Outcome {
+        if self.ledger.contains(envelope.event_id) {
+            info!(
+                "Skipping {}, event {} was already compressed",
+                envelope.data.id, envelope.event_id
+            );
+            return Outcome::Ack;
+        }
+        let Some(channel) = self.rabbitmq_client.channel().await else {
+            error!("Not connected to rabbitmq, requeueing {}", envelope.data.id);
+            return Outcome::Requeue;
+        };
+        let attempt = retry::attempt(&delivery.properties);
+        let result = process_upload(
+            &self.s3_client,
+            &channel,
+            self.config,
+            &self.retry,
+            attempt,
+            envelope.data,
+            delivery.content,
+        )
+        .await;
+        match result {
+            Ok(Handled::Compressed) => {
+                self.ledger.record(envelope.event_id);
+                Outcome::Ack
+            }
+            Ok(Handled::Settled) => Outcome::Ack,
+            // the outcome couldn't be handed to the broker, so let it redeliver
+            Err(e) => {
+                error!("{}", e);
+                Outcome::Requeue
+            }
+        }
+    }
+}
+
+/// What became of an upload whose outcome reached the broker.
+enum Handled {
+    /// Servable and announced; redeliveries have nothing left to do.
+    Compressed,
+    /// Failed, and was scheduled for a retry or reported as failed. A
+    /// redelivery, e.g. a replay from the dead letter queue, tries again.
+    Settled,
+}
+
+/// Compresses one upload and decides what happens to it. Failures the
+/// pipeline handled itself, by retrying or dead-lettering, return `Ok` so the
+/// delivery is acked; `Err` means the broker didn't confirm what it was told
+/// and the delivery should be redelivered.
+async fn process_upload(
+    s3_client: &Client,
+    channel: &ConfirmedChannel,
+    config: Arc<CompressionConfig>,
+    retry: &RetryPolicy,
+    attempt: u32,
+    data: MediaUploadedMessage,
+    content: Vec<u8>,
+) -> Result<Handled, String> {
+    info!("Message received: {:?} (attempt {})", data, attempt);
+    let failure = match compress_upload(s3_client, config, &data).await {
+        Ok(message) => {
+            info!("Upload {} finished as {}", message.id, message.status);
+            match publish(channel, message).await {
+                Ok(()) => return Ok(Handled::Compressed),
+                Err(e) => UploadFailure::retryable("publish_failed", e),
+            }
+        }
+        Err(failure) => failure,
+    };
+
+    if failure.retryable && attempt < retry.max_attempts {
+        error!(
+            "{} attempt {} failed, retrying in {:?}: {}",
+            data.id,
+            attempt,
+            retry.delay(attempt),
+            failure.message
+        );
+        retry.schedule(channel, content, attempt).await?;
+        return Ok(Handled::Settled);
+    }
+    error!("{} failed: {}", data.id, failure.message);
+    if failure.retryable {
+        // out of attempts, keep it around to replay once the cause is fixed
+        retry::dead_letter(channel, content, attempt, failure.code, &failure.message).await?;
+    }
+    publish(
+        channel,
+        MediaCompressionFailedMessage {
+            id: data.id,
+            compressed_id: data.compressed_id,
+            code: failure.code.to_string(),
+            message: failure.message,
+        },
+    )
+    .await?;
+    Ok(Handled::Settled)
+}
+
+/// Why an upload could not be compressed, reported back to media-service.
+struct UploadFailure {
+    code: &'static str,
+    message: String,
+    /// Whether another attempt could succeed, e.g. S3 being briefly unavailable.
+    retryable: bool,
+}
+
+impl UploadFailure {
+    fn new(code: &'static str, message: String) -> Self {
+        Self {
+            code,
+            message,
+            retryable: false,
+        }
+    }
+
+    fn retryable(code: &'static str, message: String) -> Self {
+        Self {
+            code,
+            message,
+            retryable: true,
+        }
+    }
+}
+
+impl From<CompressionError> for UploadFailure {
+    fn from(err: CompressionError) -> Self {
+        match err {
+            CompressionError::TooLarge(e) => Self::new("image_too_large", e),
+            CompressionError::Decode(e) => Self::new("decode_failed", e),
+            CompressionError::Failed(e) => Self::new("compression_failed", e),
+        }
+    }
+}
+
+/// Brings an upload to a servable state, either with renditions or, for
+/// sources that don't need any, as a passthrough of the original.
+#[instrument(skip_all, fields(media.id = %data.id))]
+async fn compress_upload(
+    s3_client: &Client,
+    config: Arc<CompressionConfig>,
+    data: &MediaUploadedMessage,
+) -> Result<MediaCompressedMessage, UploadFailure> {
+    if let Some(message) = load_manifest(s3_client, data).await {
+        info!(
+            "Upload {} was already compressed, reusing its manifest",
+            data.id
+        );
+        return Ok(message);
+    }
+    let img = s3_client
+        .get_object()
+        .bucket("media-service")
+        .key(&data.id)
+        .send()
+        .await
+        .map_err(|e| {
+            UploadFailure::retryable(
+                "download_failed",
+                format!("Failed to retrieve image from S3: {}", e),
+            )
+        })?;
+    match img.content_length() {
+        Some(size) if size as u64 > config.limits.max_bytes => {
+            return Err(CompressionError::TooLarge(format!(
+                "{} bytes exceeds {} bytes",
+                size, config.limits.max_bytes
+            ))
+            .into());
+        }
+        Some(_) => {}
+        None => {
+            return Err(UploadFailure::new(
+                "download_failed",
+                "Image has no content length".to_string(),
+            ))
+        }
+    }
+    let img_bytes = img.body.collect().await.map_err(|e| {
+        UploadFailure::retryable(
+            "download_failed",
+            format!("Failed to collect image data: {}", e),
+        )
+    })?;
+
+    // decoding, resizing and encoding are CPU bound, keep them off the async workers
+    let span = info_span!("process_image");
+    let processed = tokio::task::spawn_blocking(move || {
+        span.in_scope(|| process_image(&img_bytes.to_vec(), &config))
+    })
+    .await
+    .map_err(|e| {
+        UploadFailure::new(
+            "compression_failed",
+            format!("Compression task failed: {}", e),
+        )
+    })??;
+
+    let compressed_img = match processed {
+        ProcessedImage::Passthrough { width, height } => {
+            let message = MediaCompressedMessage {
+                id: data.id.clone(),
+                compressed_id: data.compressed_id.clone(),
+                status: "passthrough".to_string(),
+                width: Some(width),
+                height: Some(height),
+                renditions: Vec::new(),
+            };
+            store_manifest(s3_client, &message).await;
+            return Ok(message);
+        }
+        ProcessedImage::Compressed(compressed_img) => compressed_img,
+    };
+    let renditions = upload_renditions(s3_client, &data.compressed_id, compressed_img.renditions)
+        .await
+        .map_err(|e| {
+            UploadFailure::retryable(
+                "upload_failed",
+                format!("Failed to upload compressed image: {}", e),
+            )
+        })?;
+    let message = MediaCompressedMessage {
+        id: data.id.clone(),
+        compressed_id: data.compressed_id.clone(),
+        status: "compressed".to_string(),
+        width: Some(compressed_img.original_width),
+        height: Some(compressed_img.original_height),
+        renditions,
+    };
+    store_manifest(s3_client, &message).await;
+    Ok(message)
+}
+
+/// Written after every rendition of an upload is in S3, so its presence means
+/// the work is done and the message it holds can be published again as is.
+fn manifest_key(compressed_id: &str) -> String {
+    format!("{}/manifest.json", compressed_id)
+}
+
+/// The result of an earlier attempt at `data`, if one got as far as
+/// storing its manifest. Any error just means the work is redone.
+async fn load_manifest(
+    s3_client: &Client,
+    data: &MediaUploadedMessage,
+) -> Option<MediaCompressedMessage> {
+    let object = s3_client
+        .get_object()
+        .bucket("media-service")
+        .key(manifest_key(&data.compressed_id))
+        .send()
+        .await
+        .ok()?;
+    let bytes = object.body.collect().await.ok()?.into_bytes();
+    match serde_json::from_slice::<MediaCompressedMessage>(&bytes) {
+        Ok(message) if message.id == data.id => Some(message),
+        Ok(_) => None,
+        Err(e) => {
+            error!("Ignoring unreadable manifest for {}: {}", data.id, e);
+            None
+        }
     }
 }
 
+/// A missing manifest only costs a recompression on redelivery, so failing
+/// to write one doesn't fail the upload.
+async fn store_manifest(s3_client: &Client, message: &MediaCompressedMessage) {
+    let body = match serde_json::to_vec(message) {
+        Ok(body) => body,
+        Err(e) => {
+            error!("Failed to encode manifest for {}: {}", message.id, e);
+            return;
+        }
+    };
+    if let Err(e) = s3_client
+        .put_object()
+        .bucket("media-service")
+        .key(manifest_key(&message.compressed_id))
+        .body(body.into())
+        .content_type("application/json")
+        .send()
+        .await
+    {
+        error!("Failed to store manifest for {}: {}", message.id, e);
+    }
+}
+
+/// Publishes `message` to media-service, returning once the broker confirmed it.
+async fn publish<E: Event>(channel: &ConfirmedChannel, message: E) -> Result<(), String> {
+    let content = media_events::encode(PRODUCER, message).map_err(|e| e.to_string())?;
+    channel
+        .publish("media_events", E::TYPE, content)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[derive(Debug)]
-pub struct CompressionError(String);
+pub enum CompressionError {
+    /// The source exceeds the decode limits and was never decoded.
+    TooLarge(String),
+    /// The source is not an image we can read.
+    Decode(String),
+    Failed(String),
+}
+
+pub struct EncodedRendition {
+    pub name: String,
+    pub format: OutputFormat,
+    pub bytes: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub struct CompressedImage {
+    pub renditions: Vec<EncodedRendition>,
+    pub original_width: u32,
+    pub original_height: u32,
+}
+
+/// rav1e is slow at its default speed, trade some size for staying inside our CPU limit.
+const AVIF_SPEED: u8 = 8;
+
+pub enum ProcessedImage {
+    Passthrough { width: u32, height: u32 },
+    Compressed(CompressedImage),
+}
 
-fn compress_image(image_data: Vec<u8>) -> Result<Vec<u8>, CompressionError> {
-    let image = image::load_from_memory(&image_data)
-        .map_err(|e| CompressionError(format!("Failed to map image to dynamic image: {}", e)))?;
+/// Reads the headers, checks them against the decode limits and decides
+/// whether the upload needs renditions at all before any pixels are decoded.
+fn process_image(
+    image_data: &[u8],
+    config: &CompressionConfig,
+) -> Result<ProcessedImage, CompressionError> {
+    let probe = probe::probe(image_data)
+        .map_err(|e| CompressionError::Decode(format!("Failed to read image headers: {}", e)))?;
+    if probe.pixels() > config.limits.max_pixels {
+        return Err(CompressionError::TooLarge(format!(
+            "{}x{} with {} frame(s) exceeds {} pixels",
+            probe.width, probe.height, probe.frames, config.limits.max_pixels
+        )));
+    }
+    match plan::plan(&probe, image_data.len() as u64, config) {
+        Plan::Passthrough => Ok(ProcessedImage::Passthrough {
+            width: probe.width,
+            height: probe.height,
+        }),
+        Plan::Render => compress_image(image_data, &probe, config).map(ProcessedImage::Compressed),
+    }
+}
+
+/// Decodes the upload once and encodes every profile in every format.
+fn compress_image(
+    image_data: &[u8],
+    probe: &ImageProbe,
+    config: &CompressionConfig,
+) -> Result<CompressedImage, CompressionError> {
+    let format = probe
+        .format
+        .ok_or_else(|| CompressionError::Decode("No decoder for this image format".to_string()))?;
+    let mut reader = ImageReader::with_format(Cursor::new(image_data), format);
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(probe.width);
+    limits.max_image_height = Some(probe.height);
+    // room for a 16 bit RGBA frame, the widest buffer a decoder allocates
+    limits.max_alloc = Some(config.limits.max_pixels * 8);
+    reader.limits(limits);
+    let image = reader.decode().map_err(|e| match e {
+        ImageError::Limits(_) => CompressionError::TooLarge(e.to_string()),
+        _ => CompressionError::Decode(format!("Failed to map image to dynamic image: {}", e)),
+    })?;
 
     let (width, height) = image.dimensions();
-    let aspect_ratio = height as f32 / width as f32;
-    let new_height = (1080 as f32 * aspect_ratio).round() as u32;
-
-    let resized_img = image.resize(1080, new_height, FilterType::Lanczos3);
-    let binding = resized_img.to_rgb8();
-    let tj_image = turbojpeg::Image {
-        pixels: binding.as_raw().as_slice(),
-        width: resized_img.width() as usize,
-        pitch: (resized_img.width() * PixelFormat::RGB.size() as u32) as usize,
-        height: resized_img.height() as usize,
-        format: PixelFormat::RGB,
+    // JPEG renditions lose it, but WebP and AVIF keep transparency
+    let renditions = if image.color().has_alpha() {
+        render(&image.to_rgba8(), config)?
+    } else {
+        render(&image.to_rgb8(), config)?
     };
-    turbojpeg::compress(tj_image, 80, Subsamp::Sub2x1)
-        .map_err(|e| CompressionError(format!("Failed to compress image: {}", e)))
-        .map(|compressed_image| compressed_image.to_vec())
+    Ok(CompressedImage {
+        renditions,
+        original_width: width,
+        original_height: height,
+    })
+}
+
+/// Scales `source`, RGB or RGBA, to every profile and encodes each in every format.
+fn render<P>(
+    source: &ImageBuffer<P, Vec<u8>>,
+    config: &CompressionConfig,
+) -> Result<Vec<EncodedRendition>, CompressionError>
+where
+    P: Pixel<Subpixel = u8> + 'static,
+{
+    let (width, height) = source.dimensions();
+    let mut renditions = Vec::with_capacity(config.profiles.len() * config.formats.len());
+    for profile in &config.profiles {
+        let (new_width, new_height) = profile.fit(width, height);
+        // sources that already fit are only re-encoded
+        let pixels = if (new_width, new_height) == (width, height) {
+            Cow::Borrowed(source)
+        } else {
+            Cow::Owned(
+                config
+                    .resize_backend
+                    .resize(source, new_width, new_height)
+                    .map_err(|e| {
+                        CompressionError::Failed(format!("Failed to resize image: {}", e.0))
+                    })?,
+            )
+        };
+        for format in &config.formats {
+            renditions.push(EncodedRendition {
+                name: profile.name.clone(),
+                format: *format,
+                bytes: encode(&pixels, *format, profile.quality)?,
+                width: pixels.width(),
+                height: pixels.height(),
+            });
+        }
+    }
+    Ok(renditions)
+}
+
+fn encode<P>(
+    pixels: &ImageBuffer<P, Vec<u8>>,
+    format: OutputFormat,
+    quality: i32,
+) -> Result<Vec<u8>, CompressionError>
+where
+    P: Pixel<Subpixel = u8>,
+{
+    let (width, height) = pixels.dimensions();
+    let alpha = P::CHANNEL_COUNT == 4;
+    match format {
+        OutputFormat::Jpeg => {
+            // turbojpeg skips the alpha byte of RGBA pixels
+            let pixel_format = if alpha {
+                PixelFormat::RGBA
+            } else {
+                PixelFormat::RGB
+            };
+            let tj_image = turbojpeg::Image {
+                pixels: pixels.as_raw().as_slice(),
+                width: width as usize,
+                pitch: width as usize * pixel_format.size(),
+                height: height as usize,
+                format: pixel_format,
+            };
+            turbojpeg::compress(tj_image, quality, Subsamp::Sub2x1)
+                .map(|compressed_image| compressed_image.to_vec())
+                .map_err(|e| CompressionError::Failed(format!("Failed to compress image: {}", e)))
+        }
+        OutputFormat::Webp => {
+            let layout = if alpha {
+                webp::PixelLayout::Rgba
+            } else {
+                webp::PixelLayout::Rgb
+            };
+            webp::Encoder::new(pixels.as_raw(), layout, width, height)
+                .encode_simple(false, quality as f32)
+                .map(|encoded| encoded.to_vec())
+                .map_err(|e| CompressionError::Failed(format!("Failed to encode webp: {:?}", e)))
+        }
+        OutputFormat::Avif => {
+            let color_type = if alpha {
+                ExtendedColorType::Rgba8
+            } else {
+                ExtendedColorType::Rgb8
+            };
+            let mut bytes = Vec::new();
+            AvifEncoder::new_with_speed_quality(
+                &mut bytes,
+                AVIF_SPEED,
+                quality.clamp(1, 100) as u8,
+            )
+            .write_image(pixels.as_raw(), width, height, color_type)
+            .map_err(|e| CompressionError::Failed(format!("Failed to encode avif: {}", e)))?;
+            Ok(bytes)
+        }
+    }
+}
+
+/// Stores each rendition under `<compressed_id>/<profile name>.<extension>` and
+/// describes what was written for the completion event.
+async fn upload_renditions(
+    s3_client: &Client,
+    compressed_id: &str,
+    renditions: Vec<EncodedRendition>,
+) -> Result<Vec<Rendition>, String> {
+    let mut uploaded = Vec::with_capacity(renditions.len());
+    for rendition in renditions {
+        let key = format!(
+            "{}/{}.{}",
+            compressed_id,
+            rendition.name,
+            rendition.format.extension()
+        );
+        let size = rendition.bytes.len() as i64;
+        s3_client
+            .put_object()
+            .bucket("media-service")
+            .key(&key)
+            .body(rendition.bytes.into())
+            .content_type(rendition.format.mime_type())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        uploaded.push(Rendition {
+            name: rendition.name,
+            format: rendition.format.mime_type().to_string(),
+            key,
+            width: rendition.width,
+            height: rendition.height,
+            size,
+        });
+    }
+    Ok(uploaded)
 }
@@ -0,0 +1,204 @@
+use std::{env, time::Duration};
+
+use amqprs::{
+    channel::{Channel, QueueDeclareArguments},
+    BasicProperties, FieldTable, FieldValue,
+};
+use media_amqp::ConfirmedChannel;
+use media_events::MEDIA_UPLOADED;
+
+/// Queue `media.uploaded` events are consumed from. The misspelling is kept
+/// so deployments keep consuming the queue they already have; only bind it,
+/// route retries and replays through the exchange instead.
+pub const COMPRESSION_QUEUE: &str = "media_compresion_service";
+/// Exchange retries and replays are published back to, so they arrive under
+/// the same routing key as a fresh upload.
+const EXCHANGE: &str = "media_events";
+/// Uploads that kept failing after every retry, kept for inspection and replay.
+pub const DEAD_LETTER_QUEUE: &str = "media_compression.dlq";
+
+/// Longest a failed upload waits before its next attempt. Also keeps every
+/// delay well inside the 32 bit millisecond range RabbitMQ accepts as a TTL.
+const MAX_DELAY: Duration = Duration::from_secs(6 * 60 * 60);
+/// Upper bound on `maxAttempts`, one delay queue is declared per attempt.
+const MAX_ATTEMPTS: u32 = 32;
+
+const ATTEMPT_HEADER: &str = "x-attempt";
+const ERROR_CODE_HEADER: &str = "x-error-code";
+const ERROR_MESSAGE_HEADER: &str = "x-error-message";
+
+/// How often and how far apart failed uploads are retried.
+///
+/// Each delay is a durable queue with a message TTL that dead-letters back
+/// onto `media_events` as `media.uploaded`, so waiting messages survive restarts and never
+/// hold a worker.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Reads `maxAttempts` (default 5, at most 32) and `retryBaseDelayMs`
+    /// (default 5000, at most six hours).
+    pub fn from_env() -> Result<Self, String> {
+        let max_attempts = match env::var("maxAttempts") {
+            Ok(value) => value
+                .parse::<u32>()
+                .ok()
+                .filter(|n| (1..=MAX_ATTEMPTS).contains(n))
+                .ok_or_else(|| format!("invalid maxAttempts: {}", value))?,
+            Err(_) => 5,
+        };
+        let base_delay = match env::var("retryBaseDelayMs") {
+            Ok(value) => value
+                .parse::<u64>()
+                .ok()
+                .filter(|n| *n > 0)
+                .map(Duration::from_millis)
+                .filter(|d| *d <= MAX_DELAY)
+                .ok_or_else(|| format!("invalid retryBaseDelayMs: {}", value))?,
+            Err(_) => Duration::from_secs(5),
+        };
+        Ok(Self {
+            max_attempts,
+            base_delay,
+        })
+    }
+
+    /// Delay before the attempt after `attempt`, doubling every time up to
+    /// `MAX_DELAY`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .checked_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY))
+    }
+
+    /// Named after its delay, since RabbitMQ refuses to redeclare a queue
+    /// with a different TTL once the retry settings change.
+    fn retry_queue(&self, attempt: u32) -> String {
+        format!(
+            "media_compression.retry.{}ms",
+            self.delay(attempt).as_millis()
+        )
+    }
+
+    /// Declares one delay queue per retry and the dead letter queue.
+    pub async fn declare(&self, channel: &Channel) -> Result<(), String> {
+        for attempt in 1..self.max_attempts {
+            let mut arguments = FieldTable::new();
+            arguments.insert(
+                "x-message-ttl".try_into().unwrap(),
+                FieldValue::l(self.delay(attempt).as_millis() as i64),
+            );
+            arguments.insert(
+                "x-dead-letter-exchange".try_into().unwrap(),
+                FieldValue::from(EXCHANGE),
+            );
+            arguments.insert(
+                "x-dead-letter-routing-key".try_into().unwrap(),
+                FieldValue::from(MEDIA_UPLOADED),
+            );
+            channel
+                .queue_declare(
+                    QueueDeclareArguments::durable_client_named(&self.retry_queue(attempt))
+                        .arguments(arguments)
+                        .finish(),
+                )
+                .await
+                .map_err(|e| format!("Error declaring retry queue: {}", e))?;
+        }
+        channel
+            .queue_declare(QueueDeclareArguments::durable_client_named(
+                DEAD_LETTER_QUEUE,
+            ))
+            .await
+            .map_err(|e| format!("Error declaring dead letter queue: {}", e))?;
+        Ok(())
+    }
+
+    /// Parks `content` in the delay queue for `attempt`, to be redelivered as the next attempt.
+    pub async fn schedule(
+        &self,
+        channel: &ConfirmedChannel,
+        content: Vec<u8>,
+        attempt: u32,
+    ) -> Result<(), String> {
+        let mut headers = FieldTable::new();
+        headers.insert(
+            ATTEMPT_HEADER.try_into().unwrap(),
+            FieldValue::l(attempt as i64 + 1),
+        );
+        publish_to_queue(channel, &self.retry_queue(attempt), headers, content).await
+    }
+}
+
+/// The attempt a delivery belongs to, counting from 1.
+pub fn attempt(properties: &BasicProperties) -> u32 {
+    match properties
+        .headers()
+        .and_then(|h| h.get(&ATTEMPT_HEADER.try_into().unwrap()))
+    {
+        Some(FieldValue::l(n)) => (*n).max(1) as u32,
+        _ => 1,
+    }
+}
+
+/// Moves `content` to the dead letter queue along with why it failed.
+pub async fn dead_letter(
+    channel: &ConfirmedChannel,
+    content: Vec<u8>,
+    attempt: u32,
+    code: &str,
+    message: &str,
+) -> Result<(), String> {
+    let mut headers = FieldTable::new();
+    headers.insert(
+        ATTEMPT_HEADER.try_into().unwrap(),
+        FieldValue::l(attempt as i64),
+    );
+    headers.insert(
+        ERROR_CODE_HEADER.try_into().unwrap(),
+        FieldValue::from(code),
+    );
+    headers.insert(
+        ERROR_MESSAGE_HEADER.try_into().unwrap(),
+        FieldValue::from(message),
+    );
+    publish_to_queue(channel, DEAD_LETTER_QUEUE, headers, content).await
+}
+
+/// Publishes `content` as a fresh `media.uploaded` event, its first attempt again.
+pub async fn replay(channel: &ConfirmedChannel, content: Vec<u8>) -> Result<(), String> {
+    channel
+        .publish(EXCHANGE, MEDIA_UPLOADED, content)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Publishes straight to `queue` through the default exchange, returning
+/// once the broker confirmed it. Retries and dead letters stay in the trace
+/// of the attempt that parked them.
+async fn publish_to_queue(
+    channel: &ConfirmedChannel,
+    queue: &str,
+    headers: FieldTable,
+    content: Vec<u8>,
+) -> Result<(), String> {
+    channel
+        .publish_with_headers("", queue, headers, content)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Reads a string header written by `dead_letter`.
+pub fn header(properties: &BasicProperties, name: &str) -> Option<String> {
+    match properties.headers()?.get(&name.try_into().ok()?)? {
+        FieldValue::S(value) => Some(value.to_string()),
+        FieldValue::l(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+pub const DEAD_LETTER_HEADERS: [&str; 3] =
+    [ATTEMPT_HEADER, ERROR_CODE_HEADER, ERROR_MESSAGE_HEADER];
@@ -0,0 +1,23 @@
+use super::{
+    format::OutputFormat, limits::DecodeLimits, profile::RenditionProfile, resize::ResizeBackend,
+};
+
+/// Everything that decides what the compression pipeline produces.
+#[derive(Debug)]
+pub struct CompressionConfig {
+    pub profiles: Vec<RenditionProfile>,
+    pub formats: Vec<OutputFormat>,
+    pub resize_backend: ResizeBackend,
+    pub limits: DecodeLimits,
+}
+
+impl CompressionConfig {
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            profiles: RenditionProfile::from_env().map_err(|e| e.0)?,
+            formats: OutputFormat::from_env().map_err(|e| e.0)?,
+            resize_backend: ResizeBackend::from_env().map_err(|e| e.0)?,
+            limits: DecodeLimits::from_env()?,
+        })
+    }
+}
@@ -0,0 +1,63 @@
+use std::env;
+
+/// An encoding every rendition is written in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Jpeg,
+    Webp,
+    Avif,
+}
+
+#[derive(Debug)]
+pub struct FormatError(pub String);
+
+impl OutputFormat {
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Webp => "image/webp",
+            OutputFormat::Avif => "image/avif",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Avif => "avif",
+        }
+    }
+
+    pub fn defaults() -> Vec<Self> {
+        vec![OutputFormat::Jpeg, OutputFormat::Webp, OutputFormat::Avif]
+    }
+
+    /// Reads formats from `renditionFormats`, a comma separated list such as
+    /// `jpeg,webp,avif`. JPEG is always produced so every client has a fallback.
+    pub fn from_env() -> Result<Vec<Self>, FormatError> {
+        let value = match env::var("renditionFormats") {
+            Ok(value) => value,
+            Err(_) => return Ok(Self::defaults()),
+        };
+        let mut formats = vec![OutputFormat::Jpeg];
+        for name in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let format = Self::parse(name)?;
+            if !formats.contains(&format) {
+                formats.push(format);
+            }
+        }
+        Ok(formats)
+    }
+
+    fn parse(name: &str) -> Result<Self, FormatError> {
+        match name.to_ascii_lowercase().as_str() {
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "webp" => Ok(OutputFormat::Webp),
+            "avif" => Ok(OutputFormat::Avif),
+            _ => Err(FormatError(format!(
+                "unsupported rendition format: {}",
+                name
+            ))),
+        }
+    }
+}
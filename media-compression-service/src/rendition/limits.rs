@@ -0,0 +1,31 @@
+use std::env;
+
+/// Upper bounds on what the pipeline will try to decode.
+#[derive(Clone, Copy, Debug)]
+pub struct DecodeLimits {
+    /// Pixels summed over every frame.
+    pub max_pixels: u64,
+    pub max_bytes: u64,
+}
+
+impl DecodeLimits {
+    /// Reads `maxImagePixels` and `maxImageBytes`. The defaults keep a single
+    /// decode well inside the container's 1 GB memory limit.
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            max_pixels: read("maxImagePixels", 40_000_000)?,
+            max_bytes: read("maxImageBytes", 50 * 1024 * 1024)?,
+        })
+    }
+}
+
+fn read(key: &str, default: u64) -> Result<u64, String> {
+    match env::var(key) {
+        Ok(value) => value
+            .parse::<u64>()
+            .ok()
+            .filter(|n| *n > 0)
+            .ok_or_else(|| format!("invalid {}: {}", key, value)),
+        Err(_) => Ok(default),
+    }
+}
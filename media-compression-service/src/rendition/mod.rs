@@ -0,0 +1,7 @@
+pub mod config;
+pub mod format;
+pub mod limits;
+pub mod plan;
+pub mod probe;
+pub mod profile;
+pub mod resize;
@@ -0,0 +1,39 @@
+use image::ImageFormat;
+
+use super::{config::CompressionConfig, probe::ImageProbe};
+
+/// Files at most this size that already fit every profile gain little from
+/// re-encoding, so the original is served as is.
+const PASSTHROUGH_MAX_BYTES: u64 = 256 * 1024;
+
+/// What to do with an upload once its headers are known.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Plan {
+    /// Serve the original, no renditions are written.
+    Passthrough,
+    Render,
+}
+
+pub fn plan(probe: &ImageProbe, size: u64, config: &CompressionConfig) -> Plan {
+    // renditions only keep the first frame, so animations are served as uploaded
+    if probe.frames > 1 {
+        return Plan::Passthrough;
+    }
+    // nothing to render from without a decoder, the original is all there is
+    let Some(format) = probe.format else {
+        return Plan::Passthrough;
+    };
+    let web_format = matches!(
+        format,
+        ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP | ImageFormat::Avif
+    );
+    let fits = config
+        .profiles
+        .iter()
+        .all(|p| p.fit(probe.width, probe.height) == (probe.width, probe.height));
+    if web_format && fits && size <= PASSTHROUGH_MAX_BYTES {
+        Plan::Passthrough
+    } else {
+        Plan::Render
+    }
+}
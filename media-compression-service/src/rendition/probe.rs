@@ -0,0 +1,125 @@
+use std::io::Cursor;
+
+use image::{ImageFormat, ImageReader};
+
+/// What an image's headers say about it, read without decoding any pixels.
+#[derive(Debug)]
+pub struct ImageProbe {
+    /// `None` for formats whose headers we can read but that we have no
+    /// decoder for, currently AVIF and HEIC.
+    pub format: Option<ImageFormat>,
+    pub width: u32,
+    pub height: u32,
+    pub frames: u32,
+}
+
+impl ImageProbe {
+    pub fn pixels(&self) -> u64 {
+        self.width as u64 * self.height as u64 * self.frames as u64
+    }
+}
+
+pub fn probe(bytes: &[u8]) -> Result<ImageProbe, String> {
+    match probe_decodable(bytes) {
+        Ok(probe) => Ok(probe),
+        Err(e) => match heif_dimensions(bytes) {
+            Some((width, height)) => Ok(ImageProbe {
+                format: None,
+                width,
+                height,
+                frames: 1,
+            }),
+            None => Err(e),
+        },
+    }
+}
+
+fn probe_decodable(bytes: &[u8]) -> Result<ImageProbe, String> {
+    let reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?;
+    let format = reader
+        .format()
+        .ok_or_else(|| "unrecognised image format".to_string())?;
+    let (width, height) = reader.into_dimensions().map_err(|e| e.to_string())?;
+    let frames = match format {
+        ImageFormat::Gif => count_gif_frames(bytes)?,
+        ImageFormat::WebP => image_webp::WebPDecoder::new(Cursor::new(bytes))
+            .map_err(|e| e.to_string())?
+            .num_frames()
+            .max(1),
+        _ => 1,
+    };
+    Ok(ImageProbe {
+        format: Some(format),
+        width,
+        height,
+        frames,
+    })
+}
+
+/// Walks the GIF's frame headers, skipping the LZW data instead of decoding it.
+fn count_gif_frames(bytes: &[u8]) -> Result<u32, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.skip_frame_decoding(true);
+    let mut decoder = options.read_info(bytes).map_err(|e| e.to_string())?;
+    let mut frames = 0;
+    while decoder
+        .read_next_frame()
+        .map_err(|e| e.to_string())?
+        .is_some()
+    {
+        frames += 1;
+    }
+    Ok(frames.max(1))
+}
+
+/// Reads the size AVIF and HEIC files declare in their `ispe` item
+/// properties. A file can declare several, for the tiles of a grid or a
+/// thumbnail, and the largest is the image itself.
+fn heif_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let (kind, _) = boxes(bytes).next()?;
+    if kind != b"ftyp" {
+        return None;
+    }
+    // `meta` is a full box, its children start after the version and flags
+    let meta = find_box(bytes, b"meta")?.get(4..)?;
+    let properties = find_box(find_box(meta, b"iprp")?, b"ipco")?;
+    boxes(properties)
+        .filter(|(kind, _)| *kind == b"ispe")
+        .filter_map(|(_, body)| {
+            let width = u32::from_be_bytes(body.get(4..8)?.try_into().ok()?);
+            let height = u32::from_be_bytes(body.get(8..12)?.try_into().ok()?);
+            Some((width, height))
+        })
+        .max_by_key(|(width, height)| *width as u64 * *height as u64)
+}
+
+fn find_box<'a>(bytes: &'a [u8], kind: &[u8]) -> Option<&'a [u8]> {
+    boxes(bytes).find(|(k, _)| *k == kind).map(|(_, body)| body)
+}
+
+/// The ISO base media boxes laid out back to back in `bytes`, as type and
+/// body. Stops at the first box that doesn't fit.
+fn boxes(bytes: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut rest = bytes;
+    std::iter::from_fn(move || {
+        let size = u32::from_be_bytes(rest.get(0..4)?.try_into().ok()?);
+        let kind = rest.get(4..8)?;
+        let (header, size) = match size {
+            // runs to the end of the enclosing box
+            0 => (8, rest.len()),
+            1 => (
+                16,
+                usize::try_from(u64::from_be_bytes(rest.get(8..16)?.try_into().ok()?)).ok()?,
+            ),
+            size => (8, size as usize),
+        };
+        if size < header || size > rest.len() {
+            return None;
+        }
+        let body = &rest[header..size];
+        rest = &rest[size..];
+        Some((kind, body))
+    })
+}
@@ -0,0 +1,108 @@
+use std::env;
+
+/// A bounding box every upload is rendered into.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RenditionProfile {
+    pub name: String,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub quality: i32,
+}
+
+const DEFAULT_QUALITY: i32 = 80;
+
+#[derive(Debug)]
+pub struct ProfileError(pub String);
+
+impl RenditionProfile {
+    fn new(name: &str, max_width: u32, max_height: u32, quality: i32) -> Self {
+        Self {
+            name: name.to_owned(),
+            max_width,
+            max_height,
+            quality,
+        }
+    }
+
+    /// The renditions the feed needs when nothing else is configured.
+    pub fn defaults() -> Vec<Self> {
+        vec![
+            Self::new("thumbnail", 150, 150, DEFAULT_QUALITY),
+            Self::new("medium", 540, 675, DEFAULT_QUALITY),
+            Self::new("large", 1080, 1350, DEFAULT_QUALITY),
+        ]
+    }
+
+    /// Reads profiles from `renditionProfiles`, a comma separated list of
+    /// `name:width[xheight][:quality]`, e.g. `thumbnail:150,large:1080x1350:75`.
+    /// A profile without a height fits into a square.
+    pub fn from_env() -> Result<Vec<Self>, ProfileError> {
+        match env::var("renditionProfiles") {
+            Ok(value) => Self::parse_list(&value),
+            Err(_) => Ok(Self::defaults()),
+        }
+    }
+
+    /// Scales `width` x `height` down to fit the box, keeping the aspect ratio.
+    /// Images already inside the box keep their size, they are never enlarged.
+    pub fn fit(&self, width: u32, height: u32) -> (u32, u32) {
+        let scale = (self.max_width as f64 / width as f64)
+            .min(self.max_height as f64 / height as f64)
+            .min(1.0);
+        if scale >= 1.0 {
+            return (width, height);
+        }
+        let scaled = |side: u32| ((side as f64 * scale).round() as u32).max(1);
+        (scaled(width), scaled(height))
+    }
+
+    fn parse_list(value: &str) -> Result<Vec<Self>, ProfileError> {
+        let profiles = value
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(Self::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if profiles.is_empty() {
+            return Err(ProfileError("no rendition profiles configured".into()));
+        }
+        // renditions are stored under the profile name, so two would overwrite each other
+        for (i, profile) in profiles.iter().enumerate() {
+            if profiles[..i].iter().any(|p| p.name == profile.name) {
+                return Err(ProfileError(format!(
+                    "duplicate rendition profile: {}",
+                    profile.name
+                )));
+            }
+        }
+        Ok(profiles)
+    }
+
+    fn parse(value: &str) -> Result<Self, ProfileError> {
+        let invalid = || ProfileError(format!("invalid rendition profile: {}", value));
+        let positive = |s: &str| s.parse::<u32>().ok().filter(|n| *n > 0);
+        let mut fields = value.split(':');
+        let name = fields
+            .next()
+            .filter(|s| !s.is_empty())
+            .ok_or_else(invalid)?;
+        let size = fields.next().ok_or_else(invalid)?;
+        let (max_width, max_height) = match size.split_once('x') {
+            Some((w, h)) => (positive(w), positive(h)),
+            None => (positive(size), positive(size)),
+        };
+        let (max_width, max_height) = max_width.zip(max_height).ok_or_else(invalid)?;
+        let quality = match fields.next() {
+            Some(q) => q
+                .parse::<i32>()
+                .ok()
+                .filter(|q| (1..=100).contains(q))
+                .ok_or_else(invalid)?,
+            None => DEFAULT_QUALITY,
+        };
+        if fields.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self::new(name, max_width, max_height, quality))
+    }
+}
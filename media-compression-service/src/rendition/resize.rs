@@ -0,0 +1,126 @@
+use std::{
+    cell::RefCell,
+    env,
+    time::{Duration, Instant},
+};
+
+use fast_image_resize::{
+    images::{Image, ImageRef},
+    FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer,
+};
+use image::{imageops, ImageBuffer, Pixel};
+use tracing::{debug, info};
+
+use crate::metrics::DurationMetric;
+
+/// Which implementation scales renditions. `Image` is the old single-threaded
+/// `image::imageops` path, kept so the two can be compared on the same host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResizeBackend {
+    Fast,
+    Image,
+}
+
+#[derive(Debug)]
+pub struct ResizeError(pub String);
+
+pub static FAST_RESIZE_TIME: DurationMetric = DurationMetric::new("resize.fast_image_resize");
+pub static IMAGE_RESIZE_TIME: DurationMetric = DurationMetric::new("resize.image");
+
+thread_local! {
+    // Resizer keeps its convolution buffers between calls, so each worker thread reuses one.
+    static RESIZER: RefCell<Resizer> = RefCell::new(Resizer::new());
+}
+
+impl ResizeBackend {
+    /// Reads `resizeBackend` (`fast` or `image`), defaulting to `fast`.
+    pub fn from_env() -> Result<Self, ResizeError> {
+        match env::var("resizeBackend").as_deref() {
+            Err(_) | Ok("fast") => Ok(ResizeBackend::Fast),
+            Ok("image") => Ok(ResizeBackend::Image),
+            Ok(other) => Err(ResizeError(format!(
+                "unsupported resize backend: {}",
+                other
+            ))),
+        }
+    }
+
+    fn metric(self) -> &'static DurationMetric {
+        match self {
+            ResizeBackend::Fast => &FAST_RESIZE_TIME,
+            ResizeBackend::Image => &IMAGE_RESIZE_TIME,
+        }
+    }
+
+    /// Scales an RGB or RGBA buffer to `width` x `height`.
+    pub fn resize<P>(
+        self,
+        src: &ImageBuffer<P, Vec<u8>>,
+        width: u32,
+        height: u32,
+    ) -> Result<ImageBuffer<P, Vec<u8>>, ResizeError>
+    where
+        P: Pixel<Subpixel = u8> + 'static,
+    {
+        let start = Instant::now();
+        let resized = match self {
+            ResizeBackend::Fast => fast_resize(src, width, height)?,
+            ResizeBackend::Image => {
+                imageops::resize(src, width, height, imageops::FilterType::Lanczos3)
+            }
+        };
+        let elapsed = start.elapsed();
+        self.metric().record(elapsed);
+        debug!(
+            "resized {}x{} to {}x{} with {:?} in {:?}",
+            src.width(),
+            src.height(),
+            width,
+            height,
+            self,
+            elapsed
+        );
+        Ok(resized)
+    }
+}
+
+fn fast_resize<P>(
+    src: &ImageBuffer<P, Vec<u8>>,
+    width: u32,
+    height: u32,
+) -> Result<ImageBuffer<P, Vec<u8>>, ResizeError>
+where
+    P: Pixel<Subpixel = u8>,
+{
+    // alpha is premultiplied while resizing, so transparent pixels don't bleed into their neighbours
+    let pixel_type = match P::CHANNEL_COUNT {
+        3 => PixelType::U8x3,
+        4 => PixelType::U8x4,
+        n => return Err(ResizeError(format!("unsupported channel count: {}", n))),
+    };
+    let src_view = ImageRef::new(src.width(), src.height(), src.as_raw(), pixel_type)
+        .map_err(|e| ResizeError(e.to_string()))?;
+    let mut dst = Image::new(width, height, pixel_type);
+    let options = ResizeOptions::new().resize_alg(ResizeAlg::Convolution(FilterType::Lanczos3));
+    RESIZER
+        .with(|resizer| resizer.borrow_mut().resize(&src_view, &mut dst, &options))
+        .map_err(|e| ResizeError(e.to_string()))?;
+    ImageBuffer::from_raw(width, height, dst.into_vec())
+        .ok_or_else(|| ResizeError("resized buffer has the wrong size".into()))
+}
+
+/// Logs the SIMD extensions the resizer picked for this CPU.
+pub fn log_cpu_extensions() {
+    let extensions = Resizer::new().cpu_extensions();
+    info!("fast_image_resize using cpu extensions: {:?}", extensions);
+}
+
+/// Reports resize timings so the two backends can be compared from the logs.
+pub async fn report_resize_timings(interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        for metric in [&FAST_RESIZE_TIME, &IMAGE_RESIZE_TIME] {
+            metric.log();
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::{env, num::NonZeroUsize, sync::Arc, thread};
+
+use tokio::sync::Semaphore;
+
+/// Bounds how many uploads are compressed at once.
+#[derive(Clone, Debug)]
+pub struct WorkerPool {
+    size: usize,
+    permits: Arc<Semaphore>,
+}
+
+impl WorkerPool {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            permits: Arc::new(Semaphore::new(size)),
+        }
+    }
+
+    /// Sizes the pool from `compressionWorkers`, defaulting to the CPUs the
+    /// container is allowed to use.
+    pub fn from_env() -> Result<Self, String> {
+        let size = match env::var("compressionWorkers") {
+            Ok(value) => value
+                .parse::<NonZeroUsize>()
+                .map_err(|_| format!("invalid compressionWorkers: {}", value))?
+                .get(),
+            Err(_) => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        };
+        Ok(Self::new(size))
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The semaphore that hands out workers, for the dispatcher to hold a
+    /// permit per upload.
+    pub fn permits(&self) -> Arc<Semaphore> {
+        self.permits.clone()
+    }
+}
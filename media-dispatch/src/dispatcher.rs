@@ -0,0 +1,207 @@
+use std::{collections::HashMap, future::Future, pin::Pin, sync::Arc};
+
+use amqprs::{
+    channel::{BasicAckArguments, BasicNackArguments, Channel},
+    consumer::AsyncConsumer,
+    BasicProperties, Deliver,
+};
+use media_events::{Envelope, Event, EventError};
+use tokio::sync::Semaphore;
+use tracing::{error, info_span, warn, Instrument};
+
+use crate::{Delivery, DispatchMetrics, Outcome};
+
+/// A delivery being handled, resolving to how it should be settled.
+pub type HandlerFuture = Pin<Box<dyn Future<Output = Outcome> + Send>>;
+/// Decodes a delivery and starts its handler, or hands the delivery back
+/// with the decode error.
+type Handler =
+    Box<dyn Fn(Delivery) -> Result<HandlerFuture, Box<(Delivery, EventError)>> + Send + Sync>;
+type MalformedHandler = Box<dyn Fn(Delivery, EventError) -> HandlerFuture + Send + Sync>;
+
+/// An [`AsyncConsumer`] that decodes each delivery as the event registered
+/// for its routing key and settles it with whatever the handler returns.
+///
+/// Deliveries without a handler are settled with the unknown outcome, acked
+/// by default so they don't cycle. Deliveries that fail to decode go to the
+/// malformed handler, which nacks by default.
+pub struct Dispatcher {
+    handlers: HashMap<&'static str, Handler>,
+    malformed: MalformedHandler,
+    unknown: Outcome,
+    permits: Option<Arc<Semaphore>>,
+    metrics: Arc<DispatchMetrics>,
+}
+
+impl Dispatcher {
+    pub fn new(metrics: Arc<DispatchMetrics>) -> Self {
+        Self {
+            handlers: HashMap::new(),
+            malformed: Box::new(|delivery, e| {
+                warn!("malformed {} delivery: {}", delivery.routing_key, e);
+                Box::pin(async { Outcome::Nack })
+            }),
+            unknown: Outcome::Ack,
+            permits: None,
+            metrics,
+        }
+    }
+
+    /// Handles deliveries routed with `E::TYPE`, replacing any earlier handler for it.
+    pub fn on<E, F, Fut>(mut self, handler: F) -> Self
+    where
+        E: Event + Send + 'static,
+        F: Fn(Envelope<E>, Delivery) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Outcome> + Send + 'static,
+    {
+        self.handlers.insert(
+            E::TYPE,
+            Box::new(
+                move |delivery| match media_events::decode::<E>(&delivery.content) {
+                    Ok(envelope) => Ok(Box::pin(handler(envelope, delivery))),
+                    Err(e) => Err(Box::new((delivery, e))),
+                },
+            ),
+        );
+        self
+    }
+
+    /// Replaces the default handling of deliveries that fail to decode.
+    pub fn on_malformed<F, Fut>(mut self, handler: F) -> Self
+    where
+        F: Fn(Delivery, EventError) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Outcome> + Send + 'static,
+    {
+        self.malformed = Box::new(move |delivery, e| Box::pin(handler(delivery, e)));
+        self
+    }
+
+    /// How to settle deliveries whose routing key has no handler.
+    pub fn on_unknown(mut self, outcome: Outcome) -> Self {
+        self.unknown = outcome;
+        self
+    }
+
+    /// Runs handlers on their own tasks, at most as many at once as `permits`
+    /// allows. Until a permit is free no further deliveries are taken, which
+    /// leaves the backlog on the broker. Without this, deliveries are handled
+    /// one at a time in the order they arrive.
+    pub fn concurrent(mut self, permits: Arc<Semaphore>) -> Self {
+        self.permits = Some(permits);
+        self
+    }
+
+    /// The routing keys with a registered handler, for binding the queue.
+    pub fn routing_keys(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.handlers.keys().copied()
+    }
+
+    /// Starts handling `delivery` and counts the outcome it ends in. Settling
+    /// it with the broker is up to the caller, the consumer does it once the
+    /// returned future resolves.
+    pub fn dispatch(&self, delivery: Delivery) -> HandlerFuture {
+        let routing_key = delivery.routing_key.clone();
+        let handling = self.route(delivery);
+        let metrics = self.metrics.clone();
+        Box::pin(async move {
+            let outcome = handling.await;
+            metrics.record(&routing_key, outcome);
+            outcome
+        })
+    }
+
+    fn route(&self, delivery: Delivery) -> HandlerFuture {
+        let Some(handler) = self.handlers.get(delivery.routing_key.as_str()) else {
+            self.metrics.record_unknown();
+            warn!("no handler for {} delivery", delivery.routing_key);
+            let outcome = self.unknown;
+            return Box::pin(async move { outcome });
+        };
+        match handler(delivery) {
+            Ok(handling) => handling,
+            Err(rejected) => {
+                let (delivery, e) = *rejected;
+                self.metrics.record_malformed();
+                (self.malformed)(delivery, e)
+            }
+        }
+    }
+}
+
+impl AsyncConsumer for Dispatcher {
+    fn consume<'life0, 'life1, 'async_trait>(
+        &'life0 mut self,
+        channel: &'life1 Channel,
+        deliver: Deliver,
+        basic_properties: BasicProperties,
+        content: Vec<u8>,
+    ) -> Pin<Box<dyn Future<Output = ()> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            let routing_key = deliver.routing_key().clone();
+            let delivery_tag = deliver.delivery_tag();
+            let span = info_span!(
+                "consume",
+                otel.kind = "consumer",
+                messaging.system = "rabbitmq",
+                messaging.destination.name = %deliver.exchange(),
+                messaging.rabbitmq.destination.routing_key = %routing_key,
+            );
+            media_telemetry::continue_from_headers(&span, basic_properties.headers());
+            let handling = span.in_scope(|| {
+                self.dispatch(Delivery {
+                    routing_key,
+                    properties: basic_properties,
+                    content,
+                    redelivered: deliver.redelivered(),
+                })
+            });
+            let channel = channel.clone();
+            let settled = async move {
+                let outcome = handling.await;
+                settle(&channel, delivery_tag, outcome).await;
+            }
+            .instrument(span);
+            match &self.permits {
+                Some(permits) => {
+                    let permit = permits
+                        .clone()
+                        .acquire_owned()
+                        .await
+                        .expect("dispatcher permits are never closed");
+                    tokio::spawn(async move {
+                        settled.await;
+                        drop(permit);
+                    });
+                }
+                None => settled.await,
+            }
+        })
+    }
+}
+
+async fn settle(channel: &Channel, delivery_tag: u64, outcome: Outcome) {
+    let result = match outcome {
+        Outcome::Ack => {
+            channel
+                .basic_ack(BasicAckArguments::new(delivery_tag, false))
+                .await
+        }
+        Outcome::Nack | Outcome::Requeue => {
+            channel
+                .basic_nack(BasicNackArguments::new(
+                    delivery_tag,
+                    false,
+                    outcome == Outcome::Requeue,
+                ))
+                .await
+        }
+    };
+    if let Err(e) = result {
+        error!("failed to settle delivery {}: {}", delivery_tag, e);
+    }
+}
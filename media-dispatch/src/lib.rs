@@ -0,0 +1,34 @@
+//! Routes deliveries from an AMQP queue to typed handlers.
+//!
+//! Handlers are registered per event with [`Dispatcher::on`] and receive the
+//! decoded [`Envelope`](media_events::Envelope). Every delivery ends in an
+//! [`Outcome`] that the dispatcher settles with the broker, including the
+//! ones nobody registered for and the ones that fail to decode.
+
+mod dispatcher;
+mod metrics;
+
+pub use dispatcher::{Dispatcher, HandlerFuture};
+pub use metrics::{DispatchMetrics, OutcomeCounts};
+
+use amqprs::BasicProperties;
+
+/// What to tell the broker once a delivery has been handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Ack,
+    /// Reject without requeueing; the queue's dead letter settings apply, if any.
+    Nack,
+    /// Reject and put the delivery back on the queue to be tried again.
+    Requeue,
+}
+
+/// A delivery as handed to a handler. Settling it is left to the dispatcher,
+/// so handlers publish on a channel of their own.
+pub struct Delivery {
+    pub routing_key: String,
+    pub properties: BasicProperties,
+    /// The raw body, for handlers that need to republish it as is.
+    pub content: Vec<u8>,
+    pub redelivered: bool,
+}
@@ -0,0 +1,80 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::Outcome;
+
+/// How the deliveries for one routing key were settled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OutcomeCounts {
+    pub acked: u64,
+    pub nacked: u64,
+    pub requeued: u64,
+}
+
+/// Counts of how deliveries were dispatched. Lives outside the dispatcher so
+/// the numbers carry over when a reconnect builds a new one.
+#[derive(Default)]
+pub struct DispatchMetrics {
+    outcomes: Mutex<BTreeMap<String, OutcomeCounts>>,
+    unknown: AtomicU64,
+    malformed: AtomicU64,
+}
+
+impl DispatchMetrics {
+    pub(crate) fn record(&self, routing_key: &str, outcome: Outcome) {
+        let mut outcomes = self.outcomes.lock().unwrap();
+        let counts = outcomes.entry(routing_key.to_string()).or_default();
+        match outcome {
+            Outcome::Ack => counts.acked += 1,
+            Outcome::Nack => counts.nacked += 1,
+            Outcome::Requeue => counts.requeued += 1,
+        }
+    }
+
+    pub(crate) fn record_unknown(&self) {
+        self.unknown.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_malformed(&self) {
+        self.malformed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Outcomes per routing key, including unknown and malformed deliveries.
+    pub fn outcomes(&self) -> BTreeMap<String, OutcomeCounts> {
+        self.outcomes.lock().unwrap().clone()
+    }
+
+    /// Deliveries whose routing key had no handler.
+    pub fn unknown(&self) -> u64 {
+        self.unknown.load(Ordering::Relaxed)
+    }
+
+    /// Deliveries whose body couldn't be decoded as the registered event.
+    pub fn malformed(&self) -> u64 {
+        self.malformed.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Display for DispatchMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (routing_key, counts) in self.outcomes() {
+            write!(
+                f,
+                "{}: acked={} nacked={} requeued={}; ",
+                routing_key, counts.acked, counts.nacked, counts.requeued
+            )?;
+        }
+        write!(
+            f,
+            "unknown={} malformed={}",
+            self.unknown(),
+            self.malformed()
+        )
+    }
+}
@@ -0,0 +1,173 @@
+//! Pins how deliveries are routed and which outcome each kind ends in,
+//! including the ones no handler was registered for and the ones that fail to
+//! decode, along with how they are counted.
+
+use std::sync::Arc;
+
+use amqprs::{BasicProperties, FieldTable, FieldValue};
+use media_dispatch::{Delivery, DispatchMetrics, Dispatcher, Outcome, OutcomeCounts};
+use media_events::{
+    Envelope, MediaDeletedMessage, MediaUploadedMessage, MEDIA_DELETED, MEDIA_UPLOADED,
+};
+
+fn delivery(routing_key: &str, content: Vec<u8>) -> Delivery {
+    Delivery {
+        routing_key: routing_key.to_string(),
+        properties: BasicProperties::default(),
+        content,
+        redelivered: false,
+    }
+}
+
+fn uploaded() -> Vec<u8> {
+    media_events::encode(
+        "test",
+        MediaUploadedMessage {
+            id: "a1".to_string(),
+            compressed_id: "b2".to_string(),
+        },
+    )
+    .unwrap()
+}
+
+fn dispatcher(metrics: &Arc<DispatchMetrics>) -> Dispatcher {
+    Dispatcher::new(metrics.clone())
+        .on(|envelope: Envelope<MediaUploadedMessage>, _| async move {
+            assert_eq!(envelope.data.id, "a1");
+            Outcome::Ack
+        })
+        .on(|_: Envelope<MediaDeletedMessage>, _| async { Outcome::Requeue })
+}
+
+#[test]
+fn lists_routing_keys_of_registered_handlers() {
+    let metrics = Arc::new(DispatchMetrics::default());
+    let mut keys: Vec<&str> = dispatcher(&metrics).routing_keys().collect();
+    keys.sort();
+    assert_eq!(keys, vec![MEDIA_DELETED, MEDIA_UPLOADED]);
+}
+
+#[tokio::test]
+async fn routes_by_routing_key() {
+    let metrics = Arc::new(DispatchMetrics::default());
+    let dispatcher = dispatcher(&metrics);
+    let outcome = dispatcher
+        .dispatch(delivery(MEDIA_UPLOADED, uploaded()))
+        .await;
+    assert_eq!(outcome, Outcome::Ack);
+    let deleted = media_events::encode(
+        "test",
+        MediaDeletedMessage {
+            id: "a1".to_string(),
+            compressed_id: "b2".to_string(),
+            user_id: 7,
+        },
+    )
+    .unwrap();
+    let outcome = dispatcher.dispatch(delivery(MEDIA_DELETED, deleted)).await;
+    assert_eq!(outcome, Outcome::Requeue);
+}
+
+#[tokio::test]
+async fn runs_the_handler_for_retried_deliveries() {
+    // retries dead-letter back onto the exchange under the original routing
+    // key, carrying the attempt in a header
+    let mut headers = FieldTable::new();
+    headers.insert("x-attempt".try_into().unwrap(), FieldValue::l(3));
+    let mut retried = delivery(MEDIA_UPLOADED, uploaded());
+    retried.properties = BasicProperties::default().with_headers(headers).finish();
+    let metrics = Arc::new(DispatchMetrics::default());
+    let outcome = Dispatcher::new(metrics.clone())
+        .on(|_: Envelope<MediaUploadedMessage>, delivery| async move {
+            let attempt = delivery
+                .properties
+                .headers()
+                .and_then(|h| h.get(&"x-attempt".try_into().unwrap()).cloned());
+            assert!(matches!(attempt, Some(FieldValue::l(3))));
+            Outcome::Requeue
+        })
+        .dispatch(retried)
+        .await;
+    assert_eq!(outcome, Outcome::Requeue);
+    assert_eq!(metrics.unknown(), 0);
+}
+
+#[tokio::test]
+async fn acks_unknown_routing_keys_by_default() {
+    let metrics = Arc::new(DispatchMetrics::default());
+    let outcome = dispatcher(&metrics)
+        .dispatch(delivery("media.unknown", uploaded()))
+        .await;
+    assert_eq!(outcome, Outcome::Ack);
+    assert_eq!(metrics.unknown(), 1);
+}
+
+#[tokio::test]
+async fn settles_unknown_routing_keys_as_configured() {
+    let metrics = Arc::new(DispatchMetrics::default());
+    let outcome = dispatcher(&metrics)
+        .on_unknown(Outcome::Nack)
+        .dispatch(delivery("media.unknown", uploaded()))
+        .await;
+    assert_eq!(outcome, Outcome::Nack);
+}
+
+#[tokio::test]
+async fn nacks_malformed_bodies_by_default() {
+    let metrics = Arc::new(DispatchMetrics::default());
+    let outcome = dispatcher(&metrics)
+        .dispatch(delivery(MEDIA_UPLOADED, b"not json".to_vec()))
+        .await;
+    assert_eq!(outcome, Outcome::Nack);
+    assert_eq!(metrics.malformed(), 1);
+}
+
+#[tokio::test]
+async fn hands_malformed_bodies_to_the_malformed_handler() {
+    let metrics = Arc::new(DispatchMetrics::default());
+    let outcome = dispatcher(&metrics)
+        .on_malformed(|delivery, _| async move {
+            assert_eq!(delivery.content, b"{}");
+            Outcome::Ack
+        })
+        .dispatch(delivery(MEDIA_UPLOADED, b"{}".to_vec()))
+        .await;
+    assert_eq!(outcome, Outcome::Ack);
+}
+
+#[tokio::test]
+async fn counts_outcomes_per_routing_key() {
+    let metrics = Arc::new(DispatchMetrics::default());
+    let dispatcher = dispatcher(&metrics);
+    for _ in 0..2 {
+        dispatcher
+            .dispatch(delivery(MEDIA_UPLOADED, uploaded()))
+            .await;
+    }
+    dispatcher
+        .dispatch(delivery(MEDIA_UPLOADED, b"not json".to_vec()))
+        .await;
+    dispatcher
+        .dispatch(delivery("media.unknown", uploaded()))
+        .await;
+
+    let outcomes = metrics.outcomes();
+    assert_eq!(
+        outcomes[MEDIA_UPLOADED],
+        OutcomeCounts {
+            acked: 2,
+            nacked: 1,
+            requeued: 0,
+        }
+    );
+    assert_eq!(
+        outcomes["media.unknown"],
+        OutcomeCounts {
+            acked: 1,
+            nacked: 0,
+            requeued: 0,
+        }
+    );
+    assert_eq!(metrics.unknown(), 1);
+    assert_eq!(metrics.malformed(), 1);
+}
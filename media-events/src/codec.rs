@@ -0,0 +1,74 @@
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::{Envelope, Event};
+
+#[derive(Debug)]
+pub enum EventError {
+    Encode(serde_json::Error),
+    Decode(serde_json::Error),
+    /// The envelope holds a different event than the one asked for.
+    UnexpectedType { expected: &'static str, found: String },
+    /// Written by a newer schema than this build understands.
+    UnsupportedVersion { event_type: String, version: u32 },
+}
+
+impl fmt::Display for EventError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventError::Encode(e) => write!(f, "failed to encode event: {}", e),
+            EventError::Decode(e) => write!(f, "failed to decode event: {}", e),
+            EventError::UnexpectedType { expected, found } => {
+                write!(f, "expected a {} event, got {}", expected, found)
+            }
+            EventError::UnsupportedVersion {
+                event_type,
+                version,
+            } => write!(f, "unsupported {} schema version {}", event_type, version),
+        }
+    }
+}
+
+impl std::error::Error for EventError {}
+
+/// Wraps `data` in a fresh envelope and serializes it.
+pub fn encode<E: Event>(producer: &str, data: E) -> Result<Vec<u8>, EventError> {
+    serde_json::to_vec(&Envelope::new(producer, data)).map_err(EventError::Encode)
+}
+
+/// Reads an `E` from `bytes`, accepting both enveloped events and the bare
+/// payloads published before envelopes were introduced.
+pub fn decode<E: Event>(bytes: &[u8]) -> Result<Envelope<E>, EventError> {
+    let value: Value = serde_json::from_slice(bytes).map_err(EventError::Decode)?;
+    let enveloped = value
+        .as_object()
+        .is_some_and(|o| o.contains_key("event_type") && o.contains_key("data"));
+    if !enveloped {
+        let data = serde_json::from_value(value).map_err(EventError::Decode)?;
+        return Ok(Envelope::legacy(data));
+    }
+
+    let envelope: Envelope<Value> = serde_json::from_value(value).map_err(EventError::Decode)?;
+    if envelope.event_type != E::TYPE {
+        return Err(EventError::UnexpectedType {
+            expected: E::TYPE,
+            found: envelope.event_type,
+        });
+    }
+    if envelope.schema_version > E::VERSION {
+        return Err(EventError::UnsupportedVersion {
+            event_type: envelope.event_type,
+            version: envelope.schema_version,
+        });
+    }
+    let data = serde_json::from_value(envelope.data).map_err(EventError::Decode)?;
+    Ok(Envelope {
+        event_id: envelope.event_id,
+        event_type: envelope.event_type,
+        schema_version: envelope.schema_version,
+        occurred_at: envelope.occurred_at,
+        producer: envelope.producer,
+        data,
+    })
+}
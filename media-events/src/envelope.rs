@@ -0,0 +1,43 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::Event;
+
+/// Metadata every event is wrapped in.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Envelope<T> {
+    pub event_id: Uuid,
+    pub event_type: String,
+    pub schema_version: u32,
+    pub occurred_at: DateTime<Utc>,
+    /// Name of the service that published the event.
+    pub producer: String,
+    pub data: T,
+}
+
+impl<T: Event> Envelope<T> {
+    pub fn new(producer: &str, data: T) -> Self {
+        Self {
+            event_id: Uuid::new_v4(),
+            event_type: T::TYPE.to_string(),
+            schema_version: T::VERSION,
+            occurred_at: Utc::now(),
+            producer: producer.to_string(),
+            data,
+        }
+    }
+
+    /// Wraps a payload published before envelopes existed. Those carried no
+    /// metadata, so it is filled in as schema version 0 from an unknown producer.
+    pub(crate) fn legacy(data: T) -> Self {
+        Self {
+            event_id: Uuid::nil(),
+            event_type: T::TYPE.to_string(),
+            schema_version: 0,
+            occurred_at: DateTime::<Utc>::UNIX_EPOCH,
+            producer: "unknown".to_string(),
+            data,
+        }
+    }
+}
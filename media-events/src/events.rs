@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Event;
+
+pub const MEDIA_UPLOADED: &str = "media.uploaded";
+pub const MEDIA_COMPRESSED: &str = "media.compressed";
+pub const MEDIA_COMPRESSION_FAILED: &str = "media.compression_failed";
+pub const MEDIA_DELETED: &str = "media.deleted";
+pub const MEDIA_STATUS_CHANGED: &str = "media.status.changed";
+
+/// An original has landed in S3 and is waiting to be compressed.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MediaUploadedMessage {
+    pub id: String,
+    pub compressed_id: String,
+}
+
+impl Event for MediaUploadedMessage {
+    const TYPE: &'static str = MEDIA_UPLOADED;
+    const VERSION: u32 = 1;
+}
+
+/// Published by the compression service once an upload is servable.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MediaCompressedMessage {
+    pub id: String,
+    pub compressed_id: String,
+    /// `compressed`, or `passthrough` when the original is served as is.
+    pub status: String,
+    /// Dimensions of the original upload.
+    #[serde(default)]
+    pub width: Option<u32>,
+    #[serde(default)]
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub renditions: Vec<Rendition>,
+}
+
+impl Event for MediaCompressedMessage {
+    const TYPE: &'static str = MEDIA_COMPRESSED;
+    const VERSION: u32 = 1;
+}
+
+/// A resized copy of the upload written to S3.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Rendition {
+    pub name: String,
+    /// MIME type the rendition is encoded in. Older events only carried JPEG.
+    #[serde(default = "jpeg")]
+    pub format: String,
+    pub key: String,
+    pub width: u32,
+    pub height: u32,
+    /// Size of the encoded object in bytes.
+    pub size: i64,
+}
+
+fn jpeg() -> String {
+    "image/jpeg".to_string()
+}
+
+/// Sent instead of `MediaCompressedMessage` when an upload can't be compressed.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MediaCompressionFailedMessage {
+    pub id: String,
+    pub compressed_id: String,
+    /// Machine readable reason, e.g. `image_too_large` or `decode_failed`.
+    pub code: String,
+    pub message: String,
+}
+
+impl Event for MediaCompressionFailedMessage {
+    const TYPE: &'static str = MEDIA_COMPRESSION_FAILED;
+    const VERSION: u32 = 1;
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MediaDeletedMessage {
+    pub id: String,
+    pub compressed_id: String,
+    pub user_id: i32,
+}
+
+impl Event for MediaDeletedMessage {
+    const TYPE: &'static str = MEDIA_DELETED;
+    const VERSION: u32 = 1;
+}
+
+/// Broadcast to every media-service replica whenever a media row changes status,
+/// so each can push it to the owner's open event streams.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct MediaStatusChangedMessage {
+    pub media_id: String,
+    pub user_id: i32,
+    pub status: String,
+}
+
+impl Event for MediaStatusChangedMessage {
+    const TYPE: &'static str = MEDIA_STATUS_CHANGED;
+    const VERSION: u32 = 1;
+}
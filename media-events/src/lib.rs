@@ -0,0 +1,23 @@
+//! Payloads published on the `media_events` exchange, shared by every
+//! service that produces or consumes them.
+//!
+//! Each payload travels inside an [`Envelope`] naming its type and schema
+//! version. Use [`encode`] and [`decode`] rather than serializing payloads by
+//! hand, so both sides agree on the wire format.
+
+mod codec;
+mod envelope;
+mod events;
+
+pub use codec::{decode, encode, EventError};
+pub use envelope::Envelope;
+pub use events::*;
+
+/// A payload that can be published on `media_events`.
+pub trait Event: serde::Serialize + serde::de::DeserializeOwned {
+    /// Routing key the event is published under, also recorded as its type.
+    const TYPE: &'static str;
+    /// Schema version written by this build. Bump it only for changes older
+    /// consumers can't read; adding an optional field doesn't need one.
+    const VERSION: u32;
+}
@@ -0,0 +1,201 @@
+//! Pins the wire format of `media_events`. Messages already sitting in queues,
+//! retry queues and the dead letter queue were written by older builds, so
+//! every shape below has to keep decoding.
+
+use media_events::{
+    decode, encode, Envelope, EventError, MediaCompressedMessage, MediaCompressionFailedMessage,
+    MediaDeletedMessage, MediaStatusChangedMessage, MediaUploadedMessage, Rendition,
+};
+
+#[test]
+fn decodes_bare_uploaded_payload() {
+    let envelope =
+        decode::<MediaUploadedMessage>(br#"{"id":"a1","compressed_id":"b2"}"#).unwrap();
+    assert_eq!(envelope.schema_version, 0);
+    assert_eq!(envelope.event_type, "media.uploaded");
+    assert_eq!(
+        envelope.data,
+        MediaUploadedMessage {
+            id: "a1".to_string(),
+            compressed_id: "b2".to_string(),
+        }
+    );
+}
+
+#[test]
+fn decodes_compressed_payload_from_before_renditions() {
+    let envelope = decode::<MediaCompressedMessage>(
+        br#"{"id":"a1","compressed_id":"b2","status":"compressed"}"#,
+    )
+    .unwrap();
+    assert_eq!(envelope.data.width, None);
+    assert_eq!(envelope.data.height, None);
+    assert!(envelope.data.renditions.is_empty());
+}
+
+#[test]
+fn renditions_without_format_are_jpeg() {
+    let envelope = decode::<MediaCompressedMessage>(
+        br#"{"id":"a1","compressed_id":"b2","status":"compressed","width":800,"height":600,
+            "renditions":[{"name":"thumbnail","key":"b2/thumbnail.jpg","width":150,"height":113,"size":4096}]}"#,
+    )
+    .unwrap();
+    assert_eq!(envelope.data.renditions[0].format, "image/jpeg");
+}
+
+#[test]
+fn decodes_bare_failure_deleted_and_status_payloads() {
+    let failed = decode::<MediaCompressionFailedMessage>(
+        br#"{"id":"a1","compressed_id":"b2","code":"decode_failed","message":"bad header"}"#,
+    )
+    .unwrap();
+    assert_eq!(failed.data.code, "decode_failed");
+    let deleted =
+        decode::<MediaDeletedMessage>(br#"{"id":"a1","compressed_id":"b2","user_id":7}"#).unwrap();
+    assert_eq!(deleted.data.user_id, 7);
+    let status = decode::<MediaStatusChangedMessage>(
+        br#"{"media_id":"a1","user_id":7,"status":"created"}"#,
+    )
+    .unwrap();
+    assert_eq!(status.data.status, "created");
+}
+
+#[test]
+fn decodes_version_1_envelope() {
+    let envelope = decode::<MediaCompressedMessage>(
+        br#"{
+            "event_id":"5b1f3c2e-8d4a-4f7e-9c3b-2a1d0e9f8c7b",
+            "event_type":"media.compressed",
+            "schema_version":1,
+            "occurred_at":"2024-10-01T12:00:00Z",
+            "producer":"media-compression-service",
+            "data":{
+                "id":"a1","compressed_id":"b2","status":"compressed","width":800,"height":600,
+                "renditions":[{"name":"large","format":"image/webp","key":"b2/large.webp",
+                               "width":800,"height":600,"size":65536}]
+            }
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(envelope.schema_version, 1);
+    assert_eq!(envelope.producer, "media-compression-service");
+    assert_eq!(
+        envelope.data.renditions,
+        vec![Rendition {
+            name: "large".to_string(),
+            format: "image/webp".to_string(),
+            key: "b2/large.webp".to_string(),
+            width: 800,
+            height: 600,
+            size: 65536,
+        }]
+    );
+}
+
+#[test]
+fn ignores_fields_added_by_newer_producers() {
+    let envelope = decode::<MediaUploadedMessage>(
+        br#"{
+            "event_id":"5b1f3c2e-8d4a-4f7e-9c3b-2a1d0e9f8c7b",
+            "event_type":"media.uploaded",
+            "schema_version":1,
+            "occurred_at":"2024-10-01T12:00:00Z",
+            "producer":"media-service",
+            "trace_id":"abc",
+            "data":{"id":"a1","compressed_id":"b2","mime_type":"image/png"}
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(envelope.data.id, "a1");
+}
+
+#[test]
+fn round_trips_every_event() {
+    fn round_trip<E>(data: E)
+    where
+        E: media_events::Event + Clone + PartialEq + std::fmt::Debug,
+    {
+        let bytes = encode("test", data.clone()).unwrap();
+        let envelope: Envelope<E> = decode(&bytes).unwrap();
+        assert_eq!(envelope.data, data);
+        assert_eq!(envelope.event_type, E::TYPE);
+        assert_eq!(envelope.schema_version, E::VERSION);
+        assert_eq!(envelope.producer, "test");
+    }
+
+    round_trip(MediaUploadedMessage {
+        id: "a1".to_string(),
+        compressed_id: "b2".to_string(),
+    });
+    round_trip(MediaCompressedMessage {
+        id: "a1".to_string(),
+        compressed_id: "b2".to_string(),
+        status: "passthrough".to_string(),
+        width: Some(10),
+        height: Some(20),
+        renditions: Vec::new(),
+    });
+    round_trip(MediaCompressionFailedMessage {
+        id: "a1".to_string(),
+        compressed_id: "b2".to_string(),
+        code: "image_too_large".to_string(),
+        message: "too many pixels".to_string(),
+    });
+    round_trip(MediaDeletedMessage {
+        id: "a1".to_string(),
+        compressed_id: "b2".to_string(),
+        user_id: 7,
+    });
+    round_trip(MediaStatusChangedMessage {
+        media_id: "a1".to_string(),
+        user_id: 7,
+        status: "failed".to_string(),
+    });
+}
+
+#[test]
+fn rejects_other_event_types() {
+    let bytes = encode(
+        "test",
+        MediaDeletedMessage {
+            id: "a1".to_string(),
+            compressed_id: "b2".to_string(),
+            user_id: 7,
+        },
+    )
+    .unwrap();
+    assert!(matches!(
+        decode::<MediaUploadedMessage>(&bytes),
+        Err(EventError::UnexpectedType { .. })
+    ));
+}
+
+#[test]
+fn rejects_newer_schema_versions() {
+    let result = decode::<MediaUploadedMessage>(
+        br#"{
+            "event_id":"5b1f3c2e-8d4a-4f7e-9c3b-2a1d0e9f8c7b",
+            "event_type":"media.uploaded",
+            "schema_version":2,
+            "occurred_at":"2024-10-01T12:00:00Z",
+            "producer":"media-service",
+            "data":{"upload":"a1"}
+        }"#,
+    );
+    assert!(matches!(
+        result,
+        Err(EventError::UnsupportedVersion { version: 2, .. })
+    ));
+}
+
+#[test]
+fn rejects_malformed_payloads() {
+    assert!(matches!(
+        decode::<MediaUploadedMessage>(b"not json"),
+        Err(EventError::Decode(_))
+    ));
+    assert!(matches!(
+        decode::<MediaUploadedMessage>(br#"{"id":"a1"}"#),
+        Err(EventError::Decode(_))
+    ));
+}
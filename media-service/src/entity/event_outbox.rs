@@ -0,0 +1,32 @@
+use sea_orm::entity::prelude::*;
+
+/// An event committed together with the change it announces, waiting for the
+/// outbox relay to publish it. `payload` is the encoded envelope, so every
+/// publish of the row carries the same `event_id`.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "event_outbox")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    /// Routing key the payload is published under.
+    pub event_type: String,
+    pub payload: Vec<u8>,
+    pub created_at: DateTimeUtc,
+    /// When the broker confirmed the publish; `None` while pending.
+    pub sent_at: Option<DateTimeUtc>,
+    /// Failed publishes so far.
+    pub attempts: i32,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub last_error: Option<String>,
+    /// Trace context of the request that wrote the event, so its publish
+    /// joins the same trace.
+    pub trace_context: Option<Json>,
+    /// When the relay gave up on the row, so it no longer holds back the
+    /// events behind it; `None` while it is still tried.
+    pub parked_at: Option<DateTimeUtc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
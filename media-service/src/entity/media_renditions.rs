@@ -0,0 +1,39 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// A resized copy of a `user_media` upload produced by the compression service.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "media_renditions")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    #[serde(skip_deserializing)]
+    pub id: i32,
+    pub user_media_id: i32,
+    /// Profile name, e.g. `thumbnail`.
+    pub name: String,
+    /// MIME type the rendition is encoded in.
+    pub format: String,
+    pub key: String,
+    pub width: i32,
+    pub height: i32,
+    pub size_bytes: i64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user_media::Entity",
+        from = "Column::UserMediaId",
+        to = "super::user_media::Column::Id",
+        on_delete = "Cascade"
+    )]
+    UserMedia,
+}
+
+impl Related<super::user_media::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::UserMedia.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1 +1,5 @@
+pub mod event_outbox;
+pub mod media_renditions;
+pub mod processed_events;
+pub mod tus_upload;
 pub mod user_media;
@@ -0,0 +1,35 @@
+use sea_orm::{entity::prelude::*, FromJsonQueryResult};
+use serde::{Deserialize, Serialize};
+
+/// A resumable upload in progress. `id` is the `media_id` of the `user_media`
+/// row the upload completes into, and also the S3 key being assembled.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "tus_upload")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: String,
+    pub user_id: i32,
+    pub s3_upload_id: String,
+    pub upload_length: i64,
+    pub upload_offset: i64,
+    pub parts: UploadedParts,
+    /// Received bytes not yet large enough to become an S3 part.
+    pub buffer: Vec<u8>,
+    pub expires_at: DateTimeUtc,
+    /// Until when a PATCH is writing to the upload; `None` or past while idle.
+    pub claimed_until: Option<DateTimeUtc>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, FromJsonQueryResult)]
+pub struct UploadedParts(pub Vec<UploadedPart>);
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadedPart {
+    pub part_number: i32,
+    pub e_tag: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,9 +1,9 @@
 //! SeaORM Entity. Generated by sea-orm-codegen 0.3.2
 
-use sea_orm::entity::prelude::*;
+use sea_orm::{entity::prelude::*, Set};
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
 #[sea_orm(table_name = "user_media")]
 pub struct Model {
     #[sea_orm(primary_key)]
@@ -13,6 +13,21 @@ pub struct Model {
     pub media_id: String,
     pub media_compressed_id: String,
     pub status: String,
+    pub mime_type: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub description: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub alt_text: Option<String>,
+    pub original_filename: Option<String>,
+    pub size_bytes: Option<i64>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// Why compression failed, set together with the `failed` status.
+    pub failure_code: Option<String>,
+    #[sea_orm(column_type = "Text", nullable)]
+    pub failure_message: Option<String>,
+    pub created_at: DateTimeUtc,
+    pub updated_at: DateTimeUtc,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -22,6 +37,28 @@ pub enum MediaStatus {
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
-pub enum Relation {}
+pub enum Relation {
+    #[sea_orm(has_many = "super::media_renditions::Entity")]
+    MediaRenditions,
+}
+
+impl Related<super::media_renditions::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::MediaRenditions.def()
+    }
+}
 
-impl ActiveModelBehavior for ActiveModel {}
+#[async_trait::async_trait]
+impl ActiveModelBehavior for ActiveModel {
+    async fn before_save<C>(mut self, _db: &C, insert: bool) -> Result<Self, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let now = chrono::Utc::now();
+        if insert {
+            self.created_at = Set(now);
+        }
+        self.updated_at = Set(now);
+        Ok(self)
+    }
+}
@@ -0,0 +1,49 @@
+use std::convert::Infallible;
+
+use axum::{
+    extract::State,
+    http::{HeaderName, HeaderValue},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+};
+use futures_util::{stream, Stream};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{jwt::jwt::Claims, AppState};
+
+/// Streams status changes of the caller's media as Server-Sent Events.
+///
+/// Emits a `status` event per change. If the stream falls behind and changes
+/// are dropped, a `resync` event tells the client to refetch its media list.
+pub async fn media_events(State(state): State<AppState>, claims: Claims) -> impl IntoResponse {
+    let events = status_stream(state, claims.user_id);
+    (
+        // stop nginx from buffering the stream
+        [(
+            HeaderName::from_static("x-accel-buffering"),
+            HeaderValue::from_static("no"),
+        )],
+        Sse::new(events).keep_alive(KeepAlive::default()),
+    )
+}
+
+fn status_stream(state: AppState, user_id: i32) -> impl Stream<Item = Result<Event, Infallible>> {
+    let receiver = state.status_events.subscribe();
+    stream::unfold(receiver, move |mut receiver| async move {
+        loop {
+            let event = match receiver.recv().await {
+                Ok(m) if m.user_id == user_id => Event::default()
+                    .event("status")
+                    .id(m.media_id.clone())
+                    .json_data(&m)
+                    .unwrap_or_else(|_| Event::default().event("resync")),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => Event::default().event("resync"),
+                Err(RecvError::Closed) => return None,
+            };
+            return Some((Ok(event), receiver));
+        }
+    })
+}
@@ -1,27 +1,64 @@
+use std::{collections::HashMap, time::Duration};
+
+use aws_sdk_s3::{
+    config::http::HttpResponse,
+    error::SdkError,
+    presigning::PresigningConfig,
+    primitives::{DateTime, DateTimeFormat},
+};
 use axum::{
-    body::Bytes,
-    extract::{Multipart, State},
-    response::IntoResponse,
+    body::Body,
+    extract::{multipart::Field, Multipart, Path, Query, State},
+    http::{header, response::Builder, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
-use serde::Serialize;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use media_amqp::{ConnectionState, PublishError};
+use sea_orm::{DbErr, TransactionTrait};
+use serde_json::json;
+use tokio_util::io::ReaderStream;
+use tracing::{debug, error, warn};
 use uuid::Uuid;
 
 use crate::{
-    entity::user_media::{self, MediaStatus},
+    entity::{media_renditions, user_media},
+    handlers::models::{
+        CreateUploadRequest, CreateUploadResponse, DownloadParams, ListMediaParams, MediaPage,
+        MediaResource, MediaVariant, UpdateMediaRequest,
+    },
     jwt::jwt::Claims,
-    rabbitmq_client::models::MediaUploadedMessage,
-    service, AppState,
+    media_type::{
+        detect::{self, SNIFF_LEN},
+        negotiate,
+    },
+    rabbitmq_client::models::{
+        MediaDeletedMessage, MediaStatusChangedMessage, MediaUploadedMessage, MEDIA_STATUS_CHANGED,
+    },
+    service,
+    storage::multipart::MultipartWriter,
+    AppState,
 };
-#[derive(Serialize)]
-pub struct Message {
-    pub message: String,
-}
 
-pub async fn check_health() -> impl IntoResponse {
-    Json(Message {
-        message: "Hello, Axum!".to_string(),
-    })
+pub(crate) const MEDIA_BUCKET: &str = "media-service";
+pub(crate) const MAX_UPLOAD_SIZE: i64 = 50 * 1024 * 1024;
+const PRESIGN_EXPIRY: Duration = Duration::from_secs(15 * 60);
+/// Presigned uploads still pending this long after they were reserved are
+/// abandoned, their URL having long expired.
+const PENDING_UPLOAD_EXPIRY: Duration = Duration::from_secs(60 * 60);
+const PENDING_SWEEP_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const DEFAULT_PAGE_SIZE: u64 = 20;
+const MAX_PAGE_SIZE: u64 = 100;
+
+/// Reports unhealthy while the RabbitMQ connection is down, since uploads
+/// can't be handed off for compression until it's back.
+pub async fn check_health(State(state): State<AppState>) -> impl IntoResponse {
+    let rabbitmq = state.rabbitmq_client.state();
+    let status = match rabbitmq {
+        ConnectionState::Connected => StatusCode::OK,
+        _ => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(json!({ "rabbitmq": rabbitmq.as_str() })))
 }
 
 #[derive(Debug)]
@@ -37,102 +74,873 @@ pub async fn upload_media(
     State(state): State<AppState>,
     claims: Claims,
     mut multipart: Multipart,
-) -> Result<impl IntoResponse, UploadError> {
-    println!("running handler, claims: {}", claims);
+) -> Result<impl IntoResponse, MediaError> {
+    debug!("running handler, claims: {}", claims);
     let mut description = String::new();
-    let mut image_file: Option<Bytes> = None;
-    while let Some(field) = multipart
+    let mut image: Option<(i64, &'static str)> = None;
+    let mut original_filename = None;
+    let id_full = Uuid::new_v4();
+    let compressed_id = Uuid::new_v4();
+    while let Some(mut field) = multipart
         .next_field()
         .await
         .map_err(|e| UploadError(e.to_string()))?
     {
-        let name = field
-            .name()
-            .ok_or_else(|| UploadError("name not found".to_string()));
+        let name = field.name().map(str::to_owned);
         let content_type = field
             .content_type()
+            .map(str::to_owned)
             .ok_or_else(|| UploadError("content type not found".to_string()));
 
-        match name {
-            Ok("description") => {
+        match name.as_deref() {
+            Some("description") => {
                 description = field.text().await.map_err(|e| UploadError(e.to_string()))?;
             }
-            Ok("image") => {
+            Some("image") => {
                 let content_type = content_type?;
-                if content_type != "image/jpeg" && content_type != "image/png" {
-                    // println!("{:?}", content_type);
-                    return Err(UploadError(
-                        "Invalid image type. Only JPG and PNG are allowed.".into(),
-                    ));
+                if !detect::is_allowed(&content_type) {
+                    return Err(MediaError::BadRequest(UNSUPPORTED_TYPE_MESSAGE.into()));
                 }
-
-                image_file = Some(
-                    field
-                        .bytes()
-                        .await
-                        .map_err(|e| UploadError(e.to_string()))?,
+                original_filename = field.file_name().map(str::to_owned);
+                image = Some(
+                    stream_field_to_s3(&state, &id_full.to_string(), &content_type, &mut field)
+                        .await?,
                 );
             }
             _ => {}
         }
     }
 
+    let Some((size, mime_type)) = image else {
+        return Err(MediaError::BadRequest("Missing image file.".into()));
+    };
     if description.is_empty() {
-        return Err(UploadError("Missing description".into()));
+        // the image is already in S3 by the time we know the form is incomplete
+        let _ = state
+            .s3_client
+            .delete_object()
+            .bucket(MEDIA_BUCKET)
+            .key(id_full)
+            .send()
+            .await;
+        return Err(MediaError::BadRequest("Missing description".into()));
     }
 
-    let id_full = Uuid::new_v4();
-    let compressed_id = Uuid::new_v4();
-    match image_file {
-        Some(img) => {
-            let size = img.len();
-            let result = state
+    let media = user_media::Model {
+        id: 0,
+        user_id: claims.user_id,
+        media_id: id_full.to_string(),
+        media_compressed_id: compressed_id.to_string(),
+        status: "created".to_string(),
+        mime_type: Some(mime_type.to_string()),
+        description: Some(description.clone()),
+        original_filename,
+        size_bytes: Some(size),
+        ..Default::default()
+    };
+    if let Err(e) = create_uploaded_media(&state, media).await {
+        // nothing will compress it, so don't keep an object no row points to
+        let _ = state
+            .s3_client
+            .delete_object()
+            .bucket(MEDIA_BUCKET)
+            .key(id_full)
+            .send()
+            .await;
+        return Err(MediaError::Internal(e.to_string()));
+    }
+    state.outbox.notify_one();
+    publish_status_change(
+        &state,
+        MediaStatusChangedMessage {
+            media_id: id_full.to_string(),
+            user_id: claims.user_id,
+            status: "created".to_string(),
+        },
+    )
+    .await;
+    Ok(Json(format!(
+        "Uploaded image with description: {} and Size {:?} kb",
+        description,
+        size / 1024
+    )))
+}
+
+/// Inserts the row for a finished upload and queues its compression in one
+/// transaction.
+async fn create_uploaded_media(state: &AppState, media: user_media::Model) -> Result<(), DbErr> {
+    let event = MediaUploadedMessage {
+        id: media.media_id.clone(),
+        compressed_id: media.media_compressed_id.clone(),
+    };
+    let txn = state.db_conn.begin().await?;
+    service::Mutation::create_post(&txn, media).await?;
+    service::Mutation::enqueue_event(&txn, event).await?;
+    txn.commit().await
+}
+
+/// Pipes a multipart field into S3 one part at a time, so a request never holds
+/// more than a part of the image in memory. The first bytes are sniffed before
+/// anything is written, and the upload is rejected if they don't match
+/// `declared_type`. Returns the number of bytes written and the detected type.
+async fn stream_field_to_s3(
+    state: &AppState,
+    key: &str,
+    declared_type: &str,
+    field: &mut Field<'_>,
+) -> Result<(i64, &'static str), UploadError> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    while head.len() < SNIFF_LEN {
+        match field
+            .chunk()
+            .await
+            .map_err(|e| UploadError(e.to_string()))?
+        {
+            Some(chunk) => head.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    let mime_type = check_content_type(&head, declared_type).map_err(UploadError)?;
+
+    let mut writer = MultipartWriter::create(&state.s3_client, key, mime_type)
+        .await
+        .map_err(|_| UploadError("Error uploading image".into()))?;
+    let mut size = head.len() as i64;
+    let result = async {
+        if size > MAX_UPLOAD_SIZE {
+            return Err(UploadError("Image exceeds the 50 MB limit.".into()));
+        }
+        writer
+            .write(&head)
+            .await
+            .map_err(|_| UploadError("Error uploading image".into()))?;
+        while let Some(chunk) = field
+            .chunk()
+            .await
+            .map_err(|e| UploadError(e.to_string()))?
+        {
+            size += chunk.len() as i64;
+            if size > MAX_UPLOAD_SIZE {
+                return Err(UploadError("Image exceeds the 50 MB limit.".into()));
+            }
+            writer
+                .write(&chunk)
+                .await
+                .map_err(|_| UploadError("Error uploading image".into()))?;
+        }
+        writer
+            .finish()
+            .await
+            .map_err(|_| UploadError("Error uploading image".into()))
+    }
+    .await;
+
+    match result {
+        Ok(()) => Ok((size, mime_type)),
+        Err(e) => {
+            if let Err(abort_err) = writer.abort().await {
+                error!("failed to abort multipart upload: {:?}", abort_err);
+            }
+            Err(e)
+        }
+    }
+}
+
+const UNSUPPORTED_TYPE_MESSAGE: &str =
+    "Invalid image type. Only JPEG, PNG, WebP, GIF, AVIF and HEIC are allowed.";
+
+/// Sniffs `head` and makes sure it is an allowed format matching what the client declared.
+fn check_content_type(head: &[u8], declared_type: &str) -> Result<&'static str, String> {
+    let detected = detect::detect(head).ok_or_else(|| UNSUPPORTED_TYPE_MESSAGE.to_string())?;
+    if detected != detect::normalize(declared_type) {
+        return Err(format!(
+            "Declared content type {} does not match detected {}",
+            declared_type, detected
+        ));
+    }
+    Ok(detected)
+}
+
+/// Reads the first bytes of an object a client uploaded directly and checks its
+/// real format, deleting the object when it isn't what was declared.
+pub(crate) async fn verify_stored_type(
+    state: &AppState,
+    key: &str,
+    declared_type: &str,
+) -> Result<&'static str, MediaError> {
+    let object = state
+        .s3_client
+        .get_object()
+        .bucket(MEDIA_BUCKET)
+        .key(key)
+        .range(format!("bytes=0-{}", SNIFF_LEN - 1))
+        .send()
+        .await?;
+    let head = object
+        .body
+        .collect()
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?
+        .into_bytes();
+
+    match check_content_type(&head, declared_type) {
+        Ok(mime_type) => Ok(mime_type),
+        Err(message) => {
+            state
                 .s3_client
-                .put_object()
-                .bucket("media-service")
-                .key(id_full)
-                .body(img.into())
-                .set_content_type(Some("application/jpeg".to_string()))
+                .delete_object()
+                .bucket(MEDIA_BUCKET)
+                .key(key)
                 .send()
-                .await;
-
-            match result {
-                Ok(_output) => {
-                    let _ = state
-                        .rabbitmq_client
-                        .send_message(MediaUploadedMessage {
-                            id: id_full.to_string(),
-                            compressed_id: compressed_id.to_string(),
-                        })
-                        .await;
-                    let db_result = service::Mutation::create_post(
-                        &state.db_conn,
-                        user_media::Model {
-                            id: 0,
-                            user_id: claims.user_id,
-                            media_id: id_full.to_string(),
-                            media_compressed_id: compressed_id.to_string(),
-                            status: "created".to_string(),
-                        },
-                    )
-                    .await;
-                    match db_result {
-                        Ok(s) => println!("{:?}", s),
-                        Err(e) => println!("{:?}", e),
-                    }
-                    Ok(Json(format!(
-                        "Uploaded image with description: {} and Size {:?} kb",
-                        description,
-                        size / 1024
-                    )))
+                .await?;
+            Err(MediaError::UnsupportedMediaType(message))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MediaError {
+    BadRequest(String),
+    NotFound,
+    Forbidden,
+    Conflict(String),
+    Gone,
+    PreconditionFailed(String),
+    PayloadTooLarge,
+    UnsupportedMediaType(String),
+    /// Carries the object's length when known, for `Content-Range: bytes */<len>`.
+    RangeNotSatisfiable(Option<i64>),
+    NotAcceptable,
+    /// A dependency such as RabbitMQ couldn't take the request right now.
+    Unavailable(String),
+    Internal(String),
+}
+
+impl IntoResponse for MediaError {
+    fn into_response(self) -> Response {
+        let content_range = match &self {
+            MediaError::RangeNotSatisfiable(Some(length)) => Some(format!("bytes */{}", length)),
+            _ => None,
+        };
+        let (status, error_message) = match self {
+            MediaError::BadRequest(e) => (StatusCode::BAD_REQUEST, e),
+            MediaError::NotFound => (StatusCode::NOT_FOUND, "Media not found".to_string()),
+            MediaError::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "Media belongs to another user".to_string(),
+            ),
+            MediaError::Conflict(e) => (StatusCode::CONFLICT, e),
+            MediaError::Gone => (StatusCode::GONE, "Upload has expired".to_string()),
+            MediaError::PreconditionFailed(e) => (StatusCode::PRECONDITION_FAILED, e),
+            MediaError::PayloadTooLarge => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Upload exceeds the maximum size".to_string(),
+            ),
+            MediaError::UnsupportedMediaType(e) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, e),
+            MediaError::RangeNotSatisfiable(_) => (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "Requested range not satisfiable".to_string(),
+            ),
+            MediaError::NotAcceptable => (
+                StatusCode::NOT_ACCEPTABLE,
+                "No stored format matches the Accept header".to_string(),
+            ),
+            MediaError::Unavailable(e) => {
+                error!("{}", e);
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Service temporarily unavailable".to_string(),
+                )
+            }
+            MediaError::Internal(e) => {
+                error!("{}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Internal server error".to_string(),
+                )
+            }
+        };
+        let mut response = (status, Json(json!({ "error": error_message }))).into_response();
+        if let Some(content_range) = content_range.and_then(|v| HeaderValue::from_str(&v).ok()) {
+            response
+                .headers_mut()
+                .insert(header::CONTENT_RANGE, content_range);
+        }
+        response
+    }
+}
+
+impl<E: std::fmt::Debug> From<SdkError<E, HttpResponse>> for MediaError {
+    fn from(err: SdkError<E, HttpResponse>) -> Self {
+        match err.raw_response().map(|r| r.status().as_u16()) {
+            Some(404) => MediaError::NotFound,
+            Some(416) => MediaError::RangeNotSatisfiable(None),
+            _ => MediaError::Internal(format!("{:?}", err)),
+        }
+    }
+}
+
+impl From<PublishError> for MediaError {
+    fn from(err: PublishError) -> Self {
+        MediaError::Unavailable(err.to_string())
+    }
+}
+
+impl From<UploadError> for MediaError {
+    fn from(err: UploadError) -> Self {
+        MediaError::BadRequest(err.0)
+    }
+}
+
+/// Loads the media row and makes sure it belongs to the caller.
+async fn find_owned_media(
+    state: &AppState,
+    claims: &Claims,
+    media_id: &str,
+) -> Result<user_media::Model, MediaError> {
+    let media = service::Query::find_post_by_media_id(&state.db_conn, media_id)
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?
+        .ok_or(MediaError::NotFound)?;
+    if media.user_id != claims.user_id {
+        return Err(MediaError::Forbidden);
+    }
+    Ok(media)
+}
+
+async fn find_renditions(
+    state: &AppState,
+    user_media_ids: &[i32],
+) -> Result<Vec<media_renditions::Model>, MediaError> {
+    service::Query::find_renditions_by_user_media_ids(&state.db_conn, user_media_ids)
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))
+}
+
+/// The S3 object a download serves.
+struct ObjectChoice {
+    key: String,
+    /// Whether the key was negotiated from the Accept header, which the
+    /// response then has to say it varies on.
+    negotiated: bool,
+}
+
+impl ObjectChoice {
+    fn stored(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            negotiated: false,
+        }
+    }
+}
+
+/// Picks the S3 key to serve. A named rendition wins over `variant`; without
+/// either, the largest rendition is served once compression has finished.
+/// Renditions are stored in several formats and the one `accept` prefers is used.
+async fn object_key(
+    state: &AppState,
+    media: &user_media::Model,
+    params: &DownloadParams,
+    accept: Option<&str>,
+) -> Result<ObjectChoice, MediaError> {
+    // small or animated uploads are served as uploaded, whatever was asked for
+    if media.status == "passthrough" {
+        return Ok(ObjectChoice::stored(&media.media_id));
+    }
+    if params.rendition.is_none() {
+        let compressed = media.status == "compressed";
+        match params.variant {
+            Some(MediaVariant::Original) => return Ok(ObjectChoice::stored(&media.media_id)),
+            Some(MediaVariant::Compressed) if !compressed => return Err(MediaError::NotFound),
+            None if !compressed => return Ok(ObjectChoice::stored(&media.media_id)),
+            _ => {}
+        }
+    }
+    let renditions = find_renditions(state, &[media.id]).await?;
+    let name = match (&params.rendition, renditions.first()) {
+        (Some(name), _) => name,
+        (None, Some(largest)) => &largest.name,
+        // media compressed before renditions existed only has the single compressed object
+        (None, None) => return Ok(ObjectChoice::stored(&media.media_compressed_id)),
+    };
+    let candidates: Vec<_> = renditions.iter().filter(|r| &r.name == name).collect();
+    if candidates.is_empty() {
+        return Err(MediaError::NotFound);
+    }
+    negotiate::choose(
+        accept,
+        candidates
+            .into_iter()
+            .map(|r| (r, r.format.as_str(), r.size_bytes)),
+    )
+    .map(|r| ObjectChoice {
+        key: r.key.clone(),
+        negotiated: true,
+    })
+    .ok_or(MediaError::NotAcceptable)
+}
+
+fn object_headers(
+    builder: Builder,
+    negotiated: bool,
+    content_type: Option<&str>,
+    content_length: Option<i64>,
+    e_tag: Option<&str>,
+    last_modified: Option<&DateTime>,
+) -> Builder {
+    let mut builder = builder.header(header::ACCEPT_RANGES, "bytes");
+    // the stored format of a rendition depends on the Accept header
+    if negotiated {
+        builder = builder.header(header::VARY, "Accept");
+    }
+    if let Some(content_type) = content_type {
+        builder = builder.header(header::CONTENT_TYPE, content_type);
+    }
+    if let Some(content_length) = content_length {
+        builder = builder.header(header::CONTENT_LENGTH, content_length);
+    }
+    if let Some(e_tag) = e_tag {
+        builder = builder.header(header::ETAG, e_tag);
+    }
+    if let Some(last_modified) = last_modified.and_then(|d| d.fmt(DateTimeFormat::HttpDate).ok()) {
+        builder = builder.header(header::LAST_MODIFIED, last_modified);
+    }
+    builder
+}
+
+pub async fn download_media(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(media_id): Path<String>,
+    Query(params): Query<DownloadParams>,
+    headers: HeaderMap,
+) -> Result<Response, MediaError> {
+    let media = find_owned_media(&state, &claims, &media_id).await?;
+    let accept = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok());
+    let choice = object_key(&state, &media, &params, accept).await?;
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(single_range);
+
+    let object = match state
+        .s3_client
+        .get_object()
+        .bucket(MEDIA_BUCKET)
+        .key(&choice.key)
+        .set_range(range)
+        .send()
+        .await
+    {
+        Ok(object) => object,
+        Err(e) => {
+            return Err(match MediaError::from(e) {
+                MediaError::RangeNotSatisfiable(_) => {
+                    MediaError::RangeNotSatisfiable(object_length(&state, &choice.key).await)
                 }
-                Err(_err) => {
-                    return Err(UploadError("Error uploading image".into()));
+                e => e,
+            })
+        }
+    };
+
+    let mut builder = Response::builder().status(StatusCode::OK);
+    if let Some(content_range) = object.content_range() {
+        builder = builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, content_range);
+    }
+    let builder = object_headers(
+        builder,
+        choice.negotiated,
+        object.content_type(),
+        object.content_length(),
+        object.e_tag(),
+        object.last_modified(),
+    );
+    let body = Body::from_stream(ReaderStream::new(object.body.into_async_read()));
+    builder
+        .body(body)
+        .map_err(|e| MediaError::Internal(e.to_string()))
+}
+
+/// Narrows a `Range` header to the single range S3 can serve. Several ranges
+/// are merged into one span covering them all, which RFC 9110 allows instead
+/// of a multipart response. Headers that can't be merged without knowing the
+/// object's length, like a suffix range among others, are ignored and the
+/// whole object is sent.
+fn single_range(range: &str) -> Option<String> {
+    let specs = range.trim().strip_prefix("bytes=")?;
+    if !specs.contains(',') {
+        return Some(range.to_string());
+    }
+    let mut first = u64::MAX;
+    let mut last = Some(0);
+    for spec in specs.split(',') {
+        let (start, end) = spec.trim().split_once('-')?;
+        first = first.min(start.trim().parse().ok()?);
+        last = match end.trim() {
+            "" => None,
+            end => {
+                let end: u64 = end.parse().ok()?;
+                last.map(|last: u64| last.max(end))
+            }
+        };
+    }
+    Some(match last {
+        Some(last) => format!("bytes={}-{}", first, last),
+        None => format!("bytes={}-", first),
+    })
+}
+
+/// The stored length of `key`, if S3 will tell us.
+async fn object_length(state: &AppState, key: &str) -> Option<i64> {
+    state
+        .s3_client
+        .head_object()
+        .bucket(MEDIA_BUCKET)
+        .key(key)
+        .send()
+        .await
+        .ok()?
+        .content_length()
+}
+
+pub async fn head_media(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(media_id): Path<String>,
+    Query(params): Query<DownloadParams>,
+    headers: HeaderMap,
+) -> Result<Response, MediaError> {
+    let media = find_owned_media(&state, &claims, &media_id).await?;
+    let accept = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok());
+    let choice = object_key(&state, &media, &params, accept).await?;
+
+    let object = state
+        .s3_client
+        .head_object()
+        .bucket(MEDIA_BUCKET)
+        .key(choice.key)
+        .send()
+        .await?;
+
+    object_headers(
+        Response::builder().status(StatusCode::OK),
+        choice.negotiated,
+        object.content_type(),
+        object.content_length(),
+        object.e_tag(),
+        object.last_modified(),
+    )
+    .body(Body::empty())
+    .map_err(|e| MediaError::Internal(e.to_string()))
+}
+
+/// Cursors are the id of the last row on the previous page, kept opaque to clients.
+fn encode_cursor(id: i32) -> String {
+    URL_SAFE_NO_PAD.encode(id.to_string())
+}
+
+fn decode_cursor(cursor: &str) -> Result<i32, MediaError> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|raw| String::from_utf8(raw).ok())
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| MediaError::BadRequest("Invalid cursor".to_string()))
+}
+
+pub async fn list_media(
+    State(state): State<AppState>,
+    claims: Claims,
+    Query(params): Query<ListMediaParams>,
+) -> Result<Json<MediaPage>, MediaError> {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let before_id = params.cursor.as_deref().map(decode_cursor).transpose()?;
+
+    // Fetch one extra row to find out whether another page exists.
+    let mut media = service::Query::find_user_media_before(
+        &state.db_conn,
+        claims.user_id,
+        before_id,
+        &params.statuses(),
+        limit + 1,
+    )
+    .await
+    .map_err(|e| MediaError::Internal(e.to_string()))?;
+
+    let next_cursor = if media.len() as u64 > limit {
+        media.truncate(limit as usize);
+        media.last().map(|m| encode_cursor(m.id))
+    } else {
+        None
+    };
+    let ids: Vec<i32> = media.iter().map(|m| m.id).collect();
+    let mut renditions: HashMap<i32, Vec<media_renditions::Model>> = HashMap::new();
+    for rendition in find_renditions(&state, &ids).await? {
+        renditions
+            .entry(rendition.user_media_id)
+            .or_default()
+            .push(rendition);
+    }
+    Ok(Json(MediaPage {
+        items: media
+            .into_iter()
+            .map(|m| {
+                let own = renditions.remove(&m.id).unwrap_or_default();
+                MediaResource::from(m).with_renditions(own)
+            })
+            .collect(),
+        next_cursor,
+    }))
+}
+
+pub async fn delete_media(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(media_id): Path<String>,
+) -> Result<StatusCode, MediaError> {
+    let media = find_owned_media(&state, &claims, &media_id).await?;
+
+    // the objects are removed by the media.deleted consumer once this commits,
+    // so a failed delete never leaves a row pointing at missing objects
+    let deleted = async {
+        let txn = state.db_conn.begin().await?;
+        service::Mutation::delete_user_media(&txn, media.id).await?;
+        service::Mutation::enqueue_event(
+            &txn,
+            MediaDeletedMessage {
+                id: media.media_id,
+                compressed_id: media.media_compressed_id,
+                user_id: media.user_id,
+            },
+        )
+        .await?;
+        txn.commit().await
+    };
+    deleted
+        .await
+        .map_err(|e: DbErr| MediaError::Internal(e.to_string()))?;
+    state.outbox.notify_one();
+    publish_status_change(
+        &state,
+        MediaStatusChangedMessage {
+            media_id,
+            user_id: claims.user_id,
+            status: "deleted".to_string(),
+        },
+    )
+    .await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Announces a status change to every replica so open event streams see it.
+/// The change is already committed, so a failed publish is only logged.
+pub(crate) async fn publish_status_change(state: &AppState, message: MediaStatusChangedMessage) {
+    if let Err(e) = state.rabbitmq_client.send_message(message).await {
+        warn!("failed to publish {}: {:?}", MEDIA_STATUS_CHANGED, e);
+    }
+}
+
+/// Reserves a media row and hands back a presigned PUT so the client can
+/// upload straight to S3 without streaming through this service.
+pub async fn create_upload(
+    State(state): State<AppState>,
+    claims: Claims,
+    Json(request): Json<CreateUploadRequest>,
+) -> Result<Json<CreateUploadResponse>, MediaError> {
+    if !detect::is_allowed(&request.content_type) {
+        return Err(MediaError::BadRequest(UNSUPPORTED_TYPE_MESSAGE.to_string()));
+    }
+    let content_type = detect::normalize(&request.content_type).to_string();
+    if request.size <= 0 || request.size > MAX_UPLOAD_SIZE {
+        return Err(MediaError::BadRequest(format!(
+            "Size must be between 1 and {} bytes",
+            MAX_UPLOAD_SIZE
+        )));
+    }
+
+    let media_id = Uuid::new_v4().to_string();
+    let presigning_config = PresigningConfig::expires_in(PRESIGN_EXPIRY)
+        .map_err(|e| MediaError::Internal(e.to_string()))?;
+    // content type and length are signed, so S3 rejects a PUT that doesn't match the request
+    let presigned = state
+        .s3_presign_client
+        .put_object()
+        .bucket(MEDIA_BUCKET)
+        .key(&media_id)
+        .content_type(&content_type)
+        .content_length(request.size)
+        .presigned(presigning_config)
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?;
+
+    service::Mutation::create_post(
+        &state.db_conn,
+        user_media::Model {
+            id: 0,
+            user_id: claims.user_id,
+            media_id: media_id.clone(),
+            media_compressed_id: Uuid::new_v4().to_string(),
+            status: "pending".to_string(),
+            mime_type: Some(content_type),
+            description: request.description,
+            original_filename: request.filename,
+            size_bytes: Some(request.size),
+            ..Default::default()
+        },
+    )
+    .await
+    .map_err(|e| MediaError::Internal(e.to_string()))?;
+
+    Ok(Json(CreateUploadResponse {
+        media_id,
+        upload_url: presigned.uri().to_string(),
+        headers: presigned
+            .headers()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect(),
+        expires_in: PRESIGN_EXPIRY.as_secs(),
+    }))
+}
+
+/// Confirms a presigned upload landed in S3 and starts compression.
+pub async fn complete_upload(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(media_id): Path<String>,
+) -> Result<Json<MediaResource>, MediaError> {
+    let media = find_owned_media(&state, &claims, &media_id).await?;
+    if media.status != "pending" {
+        return Err(already_completed());
+    }
+
+    let object = state
+        .s3_client
+        .head_object()
+        .bucket(MEDIA_BUCKET)
+        .key(&media.media_id)
+        .send()
+        .await?;
+    if object.content_length().unwrap_or_default() > MAX_UPLOAD_SIZE {
+        return Err(MediaError::BadRequest(
+            "Uploaded object is too large".to_string(),
+        ));
+    }
+    verify_stored_type(
+        &state,
+        &media.media_id,
+        media.mime_type.as_deref().unwrap_or_default(),
+    )
+    .await?;
+
+    let media = start_compression(&state.db_conn, &media)
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?
+        .ok_or_else(already_completed)?;
+    state.outbox.notify_one();
+    publish_status_change(&state, MediaStatusChangedMessage::from(&media)).await;
+    Ok(Json(media.into()))
+}
+
+pub(crate) fn already_completed() -> MediaError {
+    MediaError::Conflict("Upload has already been completed".to_string())
+}
+
+/// Deletes presigned uploads that were reserved but never completed, along
+/// with anything the client managed to put in S3 before giving up.
+pub async fn expire_pending_uploads(state: AppState) {
+    let mut interval = tokio::time::interval(PENDING_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let before = chrono::Utc::now()
+            - chrono::Duration::from_std(PENDING_UPLOAD_EXPIRY)
+                .expect("pending upload expiry fits in chrono");
+        let stale = match service::Query::find_stale_pending_media(&state.db_conn, before).await {
+            Ok(stale) => stale,
+            Err(e) => {
+                error!("failed to load stale pending uploads: {:?}", e);
+                continue;
+            }
+        };
+        for media in stale {
+            match service::Mutation::delete_pending_user_media(&state.db_conn, media.id).await {
+                Ok(true) => {}
+                // completed since it was loaded
+                Ok(false) => continue,
+                Err(e) => {
+                    error!(
+                        "failed to expire pending upload {}: {:?}",
+                        media.media_id, e
+                    );
+                    continue;
                 }
             }
-        }
-        None => {
-            return Err(UploadError("Missing image file.".into()));
+            if let Err(e) = state
+                .s3_client
+                .delete_object()
+                .bucket(MEDIA_BUCKET)
+                .key(&media.media_id)
+                .send()
+                .await
+            {
+                error!(
+                    "failed to delete object of expired upload {}: {:?}",
+                    media.media_id, e
+                );
+            }
         }
     }
 }
+
+/// Edits the user supplied text on a media item.
+pub async fn update_media(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(media_id): Path<String>,
+    Json(request): Json<UpdateMediaRequest>,
+) -> Result<Json<MediaResource>, MediaError> {
+    let media = find_owned_media(&state, &claims, &media_id).await?;
+    // omitted fields keep their value, empty strings clear it
+    let description = match request.description {
+        Some(d) => Some(d).filter(|d| !d.is_empty()),
+        None => media.description.clone(),
+    };
+    let alt_text = match request.alt_text {
+        Some(a) => Some(a).filter(|a| !a.is_empty()),
+        None => media.alt_text.clone(),
+    };
+    let media =
+        service::Mutation::update_user_media_details(&state.db_conn, media, description, alt_text)
+            .await
+            .map_err(|e| MediaError::Internal(e.to_string()))?;
+    let renditions = find_renditions(&state, &[media.id]).await?;
+    Ok(Json(MediaResource::from(media).with_renditions(renditions)))
+}
+
+/// Marks media whose original is fully stored as created and queues its
+/// compression, in one transaction. Returns `None` without queueing anything
+/// if the media was no longer pending, so only one completion starts it.
+pub(crate) async fn start_compression<C: TransactionTrait>(
+    db: &C,
+    media: &user_media::Model,
+) -> Result<Option<user_media::Model>, DbErr> {
+    let txn = db.begin().await?;
+    let Some(media) = service::Mutation::start_pending_user_media(&txn, &media.media_id).await?
+    else {
+        return Ok(None);
+    };
+    service::Mutation::enqueue_event(
+        &txn,
+        MediaUploadedMessage {
+            id: media.media_id.clone(),
+            compressed_id: media.media_compressed_id.clone(),
+        },
+    )
+    .await?;
+    txn.commit().await?;
+    Ok(Some(media))
+}
@@ -1 +1,4 @@
+pub mod events;
 pub mod handlers;
+pub mod models;
+pub mod tus;
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::entity::{media_renditions, user_media};
+
+/// Which stored object a download should read.
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaVariant {
+    Original,
+    Compressed,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DownloadParams {
+    pub variant: Option<MediaVariant>,
+    /// Name of a rendition profile, e.g. `thumbnail`.
+    pub rendition: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListMediaParams {
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+    /// Comma separated list of statuses to include, e.g. `created,compressed`.
+    pub status: Option<String>,
+}
+
+impl ListMediaParams {
+    pub fn statuses(&self) -> Vec<String> {
+        self.status
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_owned)
+            .collect()
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaResource {
+    pub id: i32,
+    pub media_id: String,
+    pub status: String,
+    pub mime_type: Option<String>,
+    pub description: Option<String>,
+    pub alt_text: Option<String>,
+    pub original_filename: Option<String>,
+    pub size: Option<i64>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub failure_code: Option<String>,
+    pub failure_message: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub url: String,
+    pub renditions: Vec<RenditionResource>,
+}
+
+impl MediaResource {
+    pub fn with_renditions(mut self, renditions: Vec<media_renditions::Model>) -> Self {
+        self.renditions = renditions
+            .into_iter()
+            .map(|r| RenditionResource {
+                url: format!("{}?rendition={}", self.url, r.name),
+                name: r.name,
+                format: r.format,
+                width: r.width,
+                height: r.height,
+                size: r.size_bytes,
+            })
+            .collect();
+        self
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RenditionResource {
+    pub name: String,
+    pub format: String,
+    pub width: i32,
+    pub height: i32,
+    pub size: i64,
+    pub url: String,
+}
+
+/// User-facing text for a compression failure code. The raw message the
+/// compression service reported can name internal hosts and is only logged.
+fn failure_message(code: &str) -> String {
+    match code {
+        "image_too_large" => "The image is too large to process",
+        "decode_failed" => "The file could not be read as an image",
+        "invalid_message" => "The upload could not be processed",
+        _ => "Processing failed, please try uploading again",
+    }
+    .to_owned()
+}
+
+impl From<user_media::Model> for MediaResource {
+    fn from(media: user_media::Model) -> Self {
+        Self {
+            id: media.id,
+            url: format!("/api/v1/media/{}", media.media_id),
+            media_id: media.media_id,
+            status: media.status,
+            mime_type: media.mime_type,
+            description: media.description,
+            alt_text: media.alt_text,
+            original_filename: media.original_filename,
+            size: media.size_bytes,
+            width: media.width,
+            height: media.height,
+            failure_message: media.failure_code.as_deref().map(failure_message),
+            failure_code: media.failure_code,
+            created_at: media.created_at,
+            updated_at: media.updated_at,
+            renditions: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaPage {
+    pub items: Vec<MediaResource>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateUploadRequest {
+    pub content_type: String,
+    pub size: i64,
+    pub filename: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateUploadResponse {
+    pub media_id: String,
+    pub upload_url: String,
+    /// Headers the client must send with the PUT, as they are part of the signature.
+    pub headers: HashMap<String, String>,
+    pub expires_in: u64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateMediaRequest {
+    pub description: Option<String>,
+    pub alt_text: Option<String>,
+}
@@ -0,0 +1,567 @@
+//! Resumable uploads following the tus 1.0 protocol (https://tus.io/protocols/resumable-upload).
+//!
+//! Each upload is backed by an S3 multipart upload. PATCH bodies are cut into
+//! parts by `MultipartWriter`; whatever is left over is kept on the `tus_upload`
+//! row until the next PATCH, since S3 rejects non-final parts under 5 MiB.
+//! While a PATCH streams its body it holds a short-lived claim on the row
+//! rather than a lock, so slow clients don't pin a database connection.
+
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{options, patch},
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::Utc;
+use futures_util::StreamExt;
+use sea_orm::{DatabaseTransaction, DbErr, TransactionTrait};
+use tower_http::set_header::SetResponseHeaderLayer;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::{
+    entity::{
+        tus_upload::{self, UploadedParts},
+        user_media,
+    },
+    handlers::handlers::{
+        already_completed, publish_status_change, start_compression, verify_stored_type,
+        MediaError, MAX_UPLOAD_SIZE, MEDIA_BUCKET,
+    },
+    jwt::jwt::Claims,
+    media_type::detect,
+    rabbitmq_client::models::MediaStatusChangedMessage,
+    service,
+    storage::multipart::MultipartWriter,
+    AppState,
+};
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+const UPLOAD_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(15 * 60);
+/// How long a PATCH's claim on an upload lasts without being renewed, e.g.
+/// after the replica writing it crashed.
+const CLAIM_DURATION: Duration = Duration::from_secs(60);
+/// How often a PATCH still receiving its body renews the claim.
+const CLAIM_RENEWAL: Duration = Duration::from_secs(20);
+
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+const TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+const UPLOAD_EXPIRES: HeaderName = HeaderName::from_static("upload-expires");
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/api/v1/media/tus",
+            options(tus_options).post(create_tus_upload),
+        )
+        .route(
+            "/api/v1/media/tus/:upload_id",
+            patch(patch_tus_upload)
+                .head(head_tus_upload)
+                .delete(delete_tus_upload),
+        )
+        .layer(SetResponseHeaderLayer::overriding(
+            TUS_RESUMABLE,
+            HeaderValue::from_static(TUS_VERSION),
+        ))
+}
+
+pub async fn tus_options() -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (TUS_VERSION_HEADER, TUS_VERSION.to_string()),
+            (TUS_EXTENSION, TUS_EXTENSIONS.to_string()),
+            (TUS_MAX_SIZE, MAX_UPLOAD_SIZE.to_string()),
+        ],
+    )
+}
+
+pub async fn create_tus_upload(
+    State(state): State<AppState>,
+    claims: Claims,
+    headers: HeaderMap,
+) -> Result<Response, MediaError> {
+    check_tus_resumable(&headers)?;
+    let upload_length = parse_i64_header(&headers, &UPLOAD_LENGTH)?;
+    if upload_length <= 0 {
+        return Err(MediaError::BadRequest(
+            "Upload-Length must be positive".to_string(),
+        ));
+    }
+    if upload_length > MAX_UPLOAD_SIZE {
+        return Err(MediaError::PayloadTooLarge);
+    }
+    let metadata = headers
+        .get(UPLOAD_METADATA)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let filetype = metadata_value(metadata, "filetype")
+        .ok_or_else(|| MediaError::BadRequest("Missing filetype metadata".to_string()))?;
+    if !detect::is_allowed(&filetype) {
+        return Err(MediaError::BadRequest(
+            "Invalid image type. Only JPEG, PNG, WebP, GIF, AVIF and HEIC are allowed.".to_string(),
+        ));
+    }
+    let filetype = detect::normalize(&filetype).to_string();
+
+    let media_id = Uuid::new_v4().to_string();
+    let writer = MultipartWriter::create(&state.s3_client, &media_id, &filetype).await?;
+
+    let expires_at = Utc::now()
+        + chrono::Duration::from_std(UPLOAD_EXPIRY).expect("upload expiry fits in chrono");
+    // both rows or neither, so a failure can't leave a pending media row behind
+    let created = async {
+        let txn = state.db_conn.begin().await?;
+        let upload = service::Mutation::create_tus_upload(
+            &txn,
+            tus_upload::Model {
+                id: media_id.clone(),
+                user_id: claims.user_id,
+                s3_upload_id: writer.upload_id().to_string(),
+                upload_length,
+                upload_offset: 0,
+                parts: UploadedParts::default(),
+                buffer: Vec::new(),
+                expires_at,
+                claimed_until: None,
+            },
+        )
+        .await?;
+        service::Mutation::create_post(
+            &txn,
+            user_media::Model {
+                id: 0,
+                user_id: claims.user_id,
+                media_id: media_id.clone(),
+                media_compressed_id: Uuid::new_v4().to_string(),
+                status: "pending".to_string(),
+                mime_type: Some(filetype),
+                description: metadata_value(metadata, "description"),
+                original_filename: metadata_value(metadata, "filename"),
+                size_bytes: Some(upload_length),
+                ..Default::default()
+            },
+        )
+        .await?;
+        txn.commit().await?;
+        Ok::<_, DbErr>(upload)
+    };
+    let upload = match created.await {
+        Ok(upload) => upload,
+        Err(e) => {
+            if let Err(abort) = writer.abort().await {
+                warn!("failed to abort multipart upload {}: {:?}", media_id, abort);
+            }
+            return Err(MediaError::Internal(e.to_string()));
+        }
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        [
+            (header::LOCATION, format!("/api/v1/media/tus/{}", media_id)),
+            (UPLOAD_EXPIRES, http_date(&upload)),
+        ],
+    )
+        .into_response())
+}
+
+pub async fn head_tus_upload(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, MediaError> {
+    check_tus_resumable(&headers)?;
+    let upload = find_owned_upload(&state, &claims, &upload_id).await?;
+    // clients check the offset before resuming, so this is where an upload
+    // whose object is assembled but couldn't be finished gets another try
+    if upload.upload_offset == upload.upload_length {
+        let txn = state
+            .db_conn
+            .begin()
+            .await
+            .map_err(|e| MediaError::Internal(e.to_string()))?;
+        let upload = lock_idle_upload(&txn, &upload_id).await?;
+        finish_upload(&state, txn, &upload).await?;
+    }
+    Ok((
+        StatusCode::OK,
+        [
+            (UPLOAD_OFFSET, upload.upload_offset.to_string()),
+            (UPLOAD_LENGTH, upload.upload_length.to_string()),
+            (UPLOAD_EXPIRES, http_date(&upload)),
+            (header::CACHE_CONTROL, "no-store".to_string()),
+        ],
+    )
+        .into_response())
+}
+
+pub async fn patch_tus_upload(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, MediaError> {
+    check_tus_resumable(&headers)?;
+    if headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        != Some("application/offset+octet-stream")
+    {
+        return Err(MediaError::UnsupportedMediaType(
+            "Content-Type must be application/offset+octet-stream".to_string(),
+        ));
+    }
+    let offset = parse_i64_header(&headers, &UPLOAD_OFFSET)?;
+    find_owned_upload(&state, &claims, &upload_id).await?;
+    // Claimed until the PATCH is recorded, so two requests can't both write
+    // parts from the same offset. The one that finds it taken gets a 409.
+    let upload = service::Mutation::claim_tus_upload(
+        &state.db_conn,
+        &upload_id,
+        Utc::now(),
+        claim_deadline(),
+    )
+    .await
+    .map_err(|e| MediaError::Internal(e.to_string()))?
+    .ok_or_else(|| MediaError::Conflict("Another request is writing to this upload".to_string()))?;
+    let written = write_upload(&state, &upload, offset, body).await;
+    if written.is_err() {
+        // lets the client resume right away instead of waiting out the claim
+        if let Err(e) = service::Mutation::release_tus_upload(&state.db_conn, &upload.id).await {
+            warn!("failed to release tus upload {}: {:?}", upload.id, e);
+        }
+    }
+    let offset = written?;
+    Ok((
+        StatusCode::NO_CONTENT,
+        [
+            (UPLOAD_OFFSET, offset.to_string()),
+            (UPLOAD_EXPIRES, http_date(&upload)),
+        ],
+    )
+        .into_response())
+}
+
+/// Streams a PATCH body into the upload's parts without holding a database
+/// transaction, then records the new offset, or finishes the upload, under a
+/// short row lock. Returns the offset reached.
+async fn write_upload(
+    state: &AppState,
+    upload: &tus_upload::Model,
+    offset: i64,
+    body: Body,
+) -> Result<i64, MediaError> {
+    if offset != upload.upload_offset {
+        return Err(MediaError::Conflict(format!(
+            "Upload-Offset is {}, expected {}",
+            offset, upload.upload_offset
+        )));
+    }
+
+    let mut offset = upload.upload_offset;
+    let mut writer = MultipartWriter::resume(
+        &state.s3_client,
+        &upload.id,
+        &upload.s3_upload_id,
+        upload.parts.clone(),
+        upload.buffer.clone(),
+    );
+    // A dropped connection still keeps whatever arrived, so the client can
+    // resume from the new offset instead of starting the chunk over.
+    let mut interrupted = None;
+    let mut renewed = Instant::now();
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                interrupted = Some(MediaError::BadRequest(e.to_string()));
+                break;
+            }
+        };
+        if offset + chunk.len() as i64 > upload.upload_length {
+            interrupted = Some(MediaError::PayloadTooLarge);
+            break;
+        }
+        writer.write(&chunk).await?;
+        offset += chunk.len() as i64;
+        if renewed.elapsed() >= CLAIM_RENEWAL {
+            service::Mutation::renew_tus_upload_claim(&state.db_conn, &upload.id, claim_deadline())
+                .await
+                .map_err(|e| MediaError::Internal(e.to_string()))?;
+            renewed = Instant::now();
+        }
+    }
+
+    let txn = state
+        .db_conn
+        .begin()
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?;
+    let current = service::Query::lock_tus_upload(&txn, &upload.id)
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?
+        .ok_or(MediaError::NotFound)?;
+    // the claim ran out and another request moved the upload on meanwhile
+    if current.upload_offset != upload.upload_offset {
+        return Err(MediaError::Conflict(
+            "Another request wrote to this upload".to_string(),
+        ));
+    }
+    if interrupted.is_none() && offset == upload.upload_length {
+        // an earlier PATCH may have assembled the object and failed after
+        if upload.upload_offset < upload.upload_length {
+            writer.finish().await?;
+        }
+        finish_upload(state, txn, upload).await?;
+    } else {
+        service::Mutation::update_tus_upload_progress(
+            &txn,
+            &upload.id,
+            offset,
+            writer.parts,
+            writer.buffer,
+        )
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?;
+        txn.commit()
+            .await
+            .map_err(|e| MediaError::Internal(e.to_string()))?;
+    }
+
+    match interrupted {
+        Some(e) => Err(e),
+        None => Ok(offset),
+    }
+}
+
+fn claim_deadline() -> chrono::DateTime<Utc> {
+    Utc::now() + chrono::Duration::from_std(CLAIM_DURATION).expect("claim duration fits in chrono")
+}
+
+/// Locks the upload row for a short change, refusing while a PATCH has it claimed.
+async fn lock_idle_upload(
+    txn: &DatabaseTransaction,
+    upload_id: &str,
+) -> Result<tus_upload::Model, MediaError> {
+    let upload = service::Query::lock_tus_upload(txn, upload_id)
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?
+        .ok_or(MediaError::NotFound)?;
+    if upload.claimed_until.is_some_and(|until| until > Utc::now()) {
+        return Err(MediaError::Conflict("Upload is busy".to_string()));
+    }
+    Ok(upload)
+}
+
+pub async fn delete_tus_upload(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<StatusCode, MediaError> {
+    check_tus_resumable(&headers)?;
+    let upload = find_owned_upload(&state, &claims, &upload_id).await?;
+    discard_upload(&state, &upload).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Aborts uploads that passed their `Upload-Expires` so S3 doesn't keep the
+/// orphaned parts around.
+pub async fn expire_tus_uploads(state: AppState) {
+    let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        let expired =
+            match service::Query::find_expired_tus_uploads(&state.db_conn, Utc::now()).await {
+                Ok(expired) => expired,
+                Err(e) => {
+                    error!("failed to load expired tus uploads: {:?}", e);
+                    continue;
+                }
+            };
+        for upload in expired {
+            if let Err(e) = discard_upload(&state, &upload).await {
+                error!("failed to expire tus upload {}: {:?}", upload.id, e);
+            }
+        }
+    }
+}
+
+/// Kicks off compression once the object is assembled and drops the upload
+/// state in the same transaction, so the upload row never outlives the S3
+/// multipart upload it points to. If the stored type can't be checked, the
+/// upload is kept as fully received so HEAD or PATCH can finish it later.
+async fn finish_upload(
+    state: &AppState,
+    txn: DatabaseTransaction,
+    upload: &tus_upload::Model,
+) -> Result<(), MediaError> {
+    let media = service::Query::find_post_by_media_id(&txn, &upload.id)
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?
+        .ok_or(MediaError::NotFound)?;
+    match verify_stored_type(
+        state,
+        &upload.id,
+        media.mime_type.as_deref().unwrap_or_default(),
+    )
+    .await
+    {
+        Ok(_) => {}
+        // the object can't be re-sent once assembled, so a mismatch drops the upload entirely
+        Err(e @ MediaError::UnsupportedMediaType(_)) => {
+            service::Mutation::delete_user_media(&txn, media.id)
+                .await
+                .map_err(|e| MediaError::Internal(e.to_string()))?;
+            service::Mutation::delete_tus_upload(&txn, &upload.id)
+                .await
+                .map_err(|e| MediaError::Internal(e.to_string()))?;
+            txn.commit()
+                .await
+                .map_err(|e| MediaError::Internal(e.to_string()))?;
+            return Err(e);
+        }
+        Err(e) => {
+            service::Mutation::update_tus_upload_progress(
+                &txn,
+                &upload.id,
+                upload.upload_length,
+                upload.parts.clone(),
+                Vec::new(),
+            )
+            .await
+            .map_err(|e| MediaError::Internal(e.to_string()))?;
+            txn.commit()
+                .await
+                .map_err(|e| MediaError::Internal(e.to_string()))?;
+            return Err(e);
+        }
+    }
+    let media = start_compression(&txn, &media)
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?
+        .ok_or_else(already_completed)?;
+    service::Mutation::delete_tus_upload(&txn, &upload.id)
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?;
+    txn.commit()
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?;
+    state.outbox.notify_one();
+    publish_status_change(state, MediaStatusChangedMessage::from(&media)).await;
+    Ok(())
+}
+
+async fn discard_upload(state: &AppState, upload: &tus_upload::Model) -> Result<(), MediaError> {
+    let txn = state
+        .db_conn
+        .begin()
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?;
+    // a PATCH still writing parts keeps the upload
+    let upload = lock_idle_upload(&txn, &upload.id).await?;
+    MultipartWriter::resume(
+        &state.s3_client,
+        &upload.id,
+        &upload.s3_upload_id,
+        UploadedParts::default(),
+        Vec::new(),
+    )
+    .abort()
+    .await?;
+    // an upload completed before its row could be dropped left the object behind
+    state
+        .s3_client
+        .delete_object()
+        .bucket(MEDIA_BUCKET)
+        .key(&upload.id)
+        .send()
+        .await?;
+    if let Some(media) = service::Query::find_post_by_media_id(&txn, &upload.id)
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?
+    {
+        service::Mutation::delete_pending_user_media(&txn, media.id)
+            .await
+            .map_err(|e| MediaError::Internal(e.to_string()))?;
+    }
+    service::Mutation::delete_tus_upload(&txn, &upload.id)
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?;
+    txn.commit()
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?;
+    Ok(())
+}
+
+async fn find_owned_upload(
+    state: &AppState,
+    claims: &Claims,
+    upload_id: &str,
+) -> Result<tus_upload::Model, MediaError> {
+    let upload = service::Query::find_tus_upload_by_id(&state.db_conn, upload_id)
+        .await
+        .map_err(|e| MediaError::Internal(e.to_string()))?
+        .ok_or(MediaError::NotFound)?;
+    if upload.user_id != claims.user_id {
+        return Err(MediaError::Forbidden);
+    }
+    if upload.expires_at < Utc::now() {
+        return Err(MediaError::Gone);
+    }
+    Ok(upload)
+}
+
+fn check_tus_resumable(headers: &HeaderMap) -> Result<(), MediaError> {
+    match headers.get(TUS_RESUMABLE).and_then(|v| v.to_str().ok()) {
+        Some(TUS_VERSION) => Ok(()),
+        _ => Err(MediaError::PreconditionFailed(format!(
+            "Tus-Resumable must be {}",
+            TUS_VERSION
+        ))),
+    }
+}
+
+fn parse_i64_header(headers: &HeaderMap, name: &HeaderName) -> Result<i64, MediaError> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| MediaError::BadRequest(format!("Missing or invalid {} header", name)))
+}
+
+/// Reads one key out of an `Upload-Metadata` header, whose values are base64 encoded.
+fn metadata_value(metadata: &str, key: &str) -> Option<String> {
+    metadata.split(',').find_map(|pair| {
+        let mut pair = pair.trim().splitn(2, ' ');
+        if pair.next()? != key {
+            return None;
+        }
+        let value = STANDARD.decode(pair.next()?).ok()?;
+        String::from_utf8(value).ok()
+    })
+}
+
+fn http_date(upload: &tus_upload::Model) -> String {
+    upload
+        .expires_at
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
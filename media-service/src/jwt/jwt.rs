@@ -16,6 +16,7 @@ use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::fmt::Display;
+use tracing::debug;
 
 static KEYS: Lazy<Keys> = Lazy::new(|| {
     let secret = std::env::var("jwtSecret").expect("JWT_SECRET must be set");
@@ -62,12 +63,12 @@ where
                     Ok(t.claims)
                 }
                 Err(e) => {
-                    println!("{}", e);
+                    debug!("{}", e);
                     Err(AuthError::InvalidToken)
                 }
             }
         } else {
-            println!("JWT not found");
+            debug!("JWT not found");
             return Err(AuthError::MissingToken);
         }
     }
@@ -94,7 +95,7 @@ pub fn extract_jwt(cookie_str: &str) -> Option<&str> {
 
 impl IntoResponse for AuthError {
     fn into_response(self) -> Response {
-        println!("making response, {:?}", self);
+        debug!("making response, {:?}", self);
         let (status, error_message) = match self {
             AuthError::InvalidToken => (StatusCode::BAD_REQUEST, "Invalid token"),
             AuthError::MissingToken => (StatusCode::BAD_REQUEST, "Missing token"),
@@ -1,4 +1,6 @@
-use amqprs::channel::{BasicConsumeArguments, QueueBindArguments, QueueDeclareArguments};
+use amqprs::channel::{
+    BasicConsumeArguments, Channel, QueueBindArguments, QueueDeclareArguments, QueueUnbindArguments,
+};
 use aws_config::BehaviorVersion;
 use aws_sdk_s3::config::{Credentials, Region};
 use axum::{
@@ -6,35 +8,66 @@ use axum::{
     routing::{get, post},
     Router,
 };
-use handlers::handlers::{check_health, upload_media};
+use handlers::{
+    events::media_events,
+    handlers::{
+        check_health, complete_upload, create_upload, delete_media, download_media,
+        expire_pending_uploads, head_media, list_media, update_media, upload_media,
+    },
+    tus,
+};
+use media_amqp::RabbitmqConfig;
+use media_dispatch::DispatchMetrics;
 use migration::Migrator;
-use rabbitmq_client::client::{CustomConsumer, RabbitmqClient, RabbitmqConfig};
+use rabbitmq_client::{
+    client::RabbitmqClient, consumers, models::MediaStatusChangedMessage, outbox,
+};
 use sea_orm::{Database, DatabaseConnection};
 use sea_orm_migration::MigratorTrait;
-use std::env;
-use tokio::signal;
+use std::{env, sync::Arc, time::Duration};
+use tokio::{
+    signal,
+    sync::{broadcast, Notify},
+};
 mod entity;
 mod handlers;
 mod jwt;
+mod media_type;
 mod migration;
 mod rabbitmq_client;
 mod service;
-use tower_http::limit::RequestBodyLimitLayer;
+mod storage;
+use tower_http::{
+    limit::RequestBodyLimitLayer,
+    trace::{DefaultMakeSpan, TraceLayer},
+};
+use tracing::{error, info, Level};
 
 #[derive(Clone, Debug)]
 pub struct AppState {
     s3_client: aws_sdk_s3::Client,
+    s3_presign_client: aws_sdk_s3::Client,
     rabbitmq_client: RabbitmqClient,
     db_conn: DatabaseConnection,
+    status_events: broadcast::Sender<MediaStatusChangedMessage>,
+    /// Wakes the outbox relay after a request commits an event.
+    outbox: Arc<Notify>,
 }
 
 #[tokio::main]
 async fn main() {
+    let telemetry = match media_telemetry::init("media-service") {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
     // let app_config = get_env_config();
     let app_config = match get_env_config() {
         Ok(config) => config,
         Err(e) => {
-            println!("{:?}", e);
+            error!("{:?}", e);
             return;
         }
     };
@@ -45,9 +78,7 @@ async fn main() {
         username: "guest".to_owned(),
         password: "guest".to_owned(),
         exchange: "media_events".to_owned(),
-    })
-    .await
-    .unwrap();
+    });
 
     let cred = Credentials::new(
         app_config.minio_id,
@@ -56,91 +87,179 @@ async fn main() {
         None,
         "loaded-from-custom-env",
     );
-    let s3_config = aws_sdk_s3::config::Builder::new()
-        // .endpoint_resolver(ep)
-        .endpoint_url("http://minio:9000")
-        .behavior_version(BehaviorVersion::v2024_03_28())
-        .credentials_provider(cred)
-        .region(Region::new("us-west-1"))
-        .force_path_style(true) // apply bucketname as path param instead of pre-domain
-        .build();
-
-    let client = aws_sdk_s3::Client::from_conf(s3_config);
+    let client = build_s3_client("http://minio:9000", cred.clone());
+    // presigned urls are handed to browsers, so they must use an endpoint reachable from outside
+    let presign_client = match &app_config.minio_public_endpoint {
+        Some(endpoint) => build_s3_client(endpoint, cred),
+        None => client.clone(),
+    };
 
     let db_conn = Database::connect(app_config.postgres_dsn)
         .await
         .expect("Database connection failed");
     Migrator::up(&db_conn, None).await.unwrap();
+    let (status_events, _) = broadcast::channel(256);
+    let topology_client = rabbitmq_client.clone();
+    let topology_db = db_conn.clone();
+    let topology_events = status_events.clone();
+    let topology_s3 = client.clone();
+    // shared across reconnects so the counts cover the whole process
+    let dispatch_metrics = Arc::new(DispatchMetrics::default());
+    let topology_metrics = dispatch_metrics.clone();
+    rabbitmq_client.supervise(Box::new(move |channel| {
+        let declared = declare_consumers(
+            channel,
+            topology_db.clone(),
+            topology_client.clone(),
+            topology_s3.clone(),
+            topology_events.clone(),
+            topology_metrics.clone(),
+        );
+        Box::pin(async move { declared.await.map_err(|e| e.to_string()) })
+    }));
+    tokio::spawn(report_dispatch_metrics(dispatch_metrics));
+    tokio::spawn(consumers::prune_ledger(db_conn.clone()));
+    let outbox = Arc::new(Notify::new());
+    tokio::spawn(outbox::relay(
+        db_conn.clone(),
+        rabbitmq_client.clone(),
+        outbox.clone(),
+    ));
     let state = AppState {
         s3_client: client,
+        s3_presign_client: presign_client,
         rabbitmq_client: rabbitmq_client,
         db_conn: db_conn.clone(),
+        status_events: status_events.clone(),
+        outbox,
     };
+    tokio::spawn(tus::expire_tus_uploads(state.clone()));
+    tokio::spawn(expire_pending_uploads(state.clone()));
     // build our application with a single route
     let app = Router::new()
+        .route("/api/v1/media", get(list_media))
         .route("/api/v1/media/health", get(check_health))
+        .route("/api/v1/media/events", get(media_events))
         .route("/api/v1/media/upload", post(upload_media)) // Adding the middleware
+        .route("/api/v1/media/uploads", post(create_upload))
+        .route(
+            "/api/v1/media/uploads/:media_id/complete",
+            post(complete_upload),
+        )
+        .route(
+            "/api/v1/media/:media_id",
+            get(download_media)
+                .head(head_media)
+                .patch(update_media)
+                .delete(delete_media),
+        )
+        .merge(tus::routes())
         .layer(DefaultBodyLimit::disable()) // Disable default limit to manage it manually
         .layer(RequestBodyLimitLayer::new(50 * 1024 * 1024)) // 50 MB limit/ Handle errors (see below)
-        // .layer(TraceLayer::new_for_http()) // Optional: Log requests
+        // one span per request, the root of the trace for everything it starts
+        .layer(TraceLayer::new_for_http().make_span_with(DefaultMakeSpan::new().level(Level::INFO)))
         .with_state(state);
 
     // run our app with hyper, listening globally on port 3000
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
 
-    // let db_clone = db_conn.clone();
-    tokio::spawn(async move {
-        let rabbitmq_client = RabbitmqClient::new(RabbitmqConfig {
-            host: "rabbitmq".to_owned(),
-            port: 5672,
-            username: "guest".to_owned(),
-            password: "guest".to_owned(),
-            exchange: "media_events".to_owned(),
-        })
+    info!("listening on port 8080");
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
-        let channel = rabbitmq_client.connection.open_channel(None).await;
-        let channel = match channel {
-            Ok(c) => c,
-            Err(e) => {
-                println!("{:?}", e);
-                return;
-            }
-        };
-        let (queue_name, _, _) = channel
-            .queue_declare(
-                QueueDeclareArguments::default()
-                    .queue("media_service".to_owned())
-                    .durable(true)
-                    .finish(),
-            )
-            .await
-            .unwrap()
-            .unwrap();
+    telemetry.shutdown();
+}
+
+/// Declares media-service's queues and starts its consumers on `channel`.
+/// Runs again on every reconnect.
+async fn declare_consumers(
+    channel: Channel,
+    db_conn: DatabaseConnection,
+    rabbitmq_client: RabbitmqClient,
+    s3_client: aws_sdk_s3::Client,
+    status_events: broadcast::Sender<MediaStatusChangedMessage>,
+    metrics: Arc<DispatchMetrics>,
+) -> Result<(), amqprs::error::Error> {
+    let (queue_name, _, _) = channel
+        .queue_declare(
+            QueueDeclareArguments::default()
+                .queue("media_service".to_owned())
+                .durable(true)
+                .finish(),
+        )
+        .await?
+        .unwrap();
+    channel
+        .queue_declare(QueueDeclareArguments::durable_client_named(
+            consumers::DEAD_LETTER_QUEUE,
+        ))
+        .await?;
+    let results =
+        consumers::compression_results(metrics.clone(), db_conn, rabbitmq_client, s3_client);
+    for routing_key in results.routing_keys() {
         channel
             .queue_bind(QueueBindArguments::new(
                 &queue_name,
                 "media_events",
-                "media.#",
+                routing_key,
             ))
-            .await
-            .unwrap();
-        // start consumer with given name
-        let args = BasicConsumeArguments::new(&queue_name, "example_basic_pub_sub");
+            .await?;
+    }
+    // the queue used to take every media event, including our own uploads
+    channel
+        .queue_unbind(QueueUnbindArguments::new(
+            &queue_name,
+            "media_events",
+            "media.#",
+        ))
+        .await?;
+    // start consumer with given name
+    let args = BasicConsumeArguments::new(&queue_name, "example_basic_pub_sub");
+    channel.basic_consume(results, args).await?;
 
-        let consumer = CustomConsumer {
-            db_conn: db_conn, // s3_client: client.clone(),
-        };
-        channel.basic_consume(consumer, args).await.unwrap();
-        println!("awaitng shutdown signal");
-        shutdown_signal().await;
-        println!("shutdown signal received");
-    });
-    println!("{}", "listening on post 8080".to_string());
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await
+    // every replica gets its own copy of status changes for its event streams
+    let (status_queue, _, _) = channel
+        .queue_declare(QueueDeclareArguments::exclusive_server_named())
+        .await?
         .unwrap();
+    let status = consumers::status_changes(metrics, status_events);
+    for routing_key in status.routing_keys() {
+        channel
+            .queue_bind(QueueBindArguments::new(
+                &status_queue,
+                "media_events",
+                routing_key,
+            ))
+            .await?;
+    }
+    channel
+        .basic_consume(
+            status,
+            BasicConsumeArguments::new(&status_queue, "media_status_events"),
+        )
+        .await?;
+    Ok(())
+}
+
+async fn report_dispatch_metrics(metrics: Arc<DispatchMetrics>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        info!("dispatched events: {}", metrics);
+    }
+}
+
+fn build_s3_client(endpoint: &str, cred: Credentials) -> aws_sdk_s3::Client {
+    let s3_config = aws_sdk_s3::config::Builder::new()
+        // .endpoint_resolver(ep)
+        .endpoint_url(endpoint)
+        .behavior_version(BehaviorVersion::v2024_03_28())
+        .credentials_provider(cred)
+        .region(Region::new("us-west-1"))
+        .force_path_style(true) // apply bucketname as path param instead of pre-domain
+        .build();
+    aws_sdk_s3::Client::from_conf(s3_config)
 }
 
 async fn shutdown_signal() {
@@ -170,6 +289,7 @@ async fn shutdown_signal() {
 struct AppConfig {
     minio_id: String,
     minio_secret_key: String,
+    minio_public_endpoint: Option<String>,
     postgres_dsn: String,
 }
 #[derive(Debug)]
@@ -178,11 +298,13 @@ fn get_env_config() -> Result<AppConfig, ConfigError> {
     let mut app_config = AppConfig {
         minio_id: "".to_string(),
         minio_secret_key: "".to_string(),
+        minio_public_endpoint: None,
         postgres_dsn: "".to_string(),
     };
     app_config.minio_id = env::var("minioID").map_err(|e| return ConfigError(e.to_string()))?;
     app_config.minio_secret_key =
         env::var("minioAccessKey").map_err(|e| return ConfigError(e.to_string()))?;
+    app_config.minio_public_endpoint = env::var("minioPublicEndPoint").ok();
     app_config.postgres_dsn =
         env::var("postgresDsn").map_err(|e| return ConfigError(e.to_string()))?;
     return Ok(app_config);
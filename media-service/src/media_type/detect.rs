@@ -0,0 +1,69 @@
+//! Detects image formats from their leading bytes instead of trusting the
+//! content type a client declares.
+
+/// Image types the service accepts, as stored on S3 objects and `user_media`.
+/// The compression service can't decode AVIF or HEIC, so those are served as
+/// uploaded.
+pub const ALLOWED_CONTENT_TYPES: [&str; 6] = [
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+    "image/gif",
+    "image/avif",
+    "image/heic",
+];
+
+/// How many leading bytes `detect` needs to recognise every allowed format.
+pub const SNIFF_LEN: usize = 64;
+
+const AVIF_BRANDS: [&[u8]; 2] = [b"avif", b"avis"];
+const HEIC_BRANDS: [&[u8]; 6] = [b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx"];
+
+pub fn detect(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Some("image/jpeg");
+    }
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some("image/png");
+    }
+    if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        return Some("image/gif");
+    }
+    if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    detect_isobmff(bytes)
+}
+
+/// Maps aliases clients commonly send onto the names `detect` returns.
+pub fn normalize(content_type: &str) -> &str {
+    match content_type {
+        "image/jpg" | "image/pjpeg" => "image/jpeg",
+        "image/heif" => "image/heic",
+        other => other,
+    }
+}
+
+pub fn is_allowed(content_type: &str) -> bool {
+    ALLOWED_CONTENT_TYPES.contains(&normalize(content_type))
+}
+
+/// AVIF and HEIC are both ISO base media files; the `ftyp` box lists brands
+/// that tell them apart.
+fn detect_isobmff(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.len() < 16 || &bytes[4..8] != b"ftyp" {
+        return None;
+    }
+    let box_len = u32::from_be_bytes(bytes[0..4].try_into().ok()?) as usize;
+    let end = box_len.min(bytes.len());
+    // major brand at 8..12, minor version at 12..16, compatible brands after
+    let brands = std::iter::once(&bytes[8..12]).chain(bytes.get(16..end)?.chunks_exact(4));
+    let mut heic = false;
+    for brand in brands {
+        if AVIF_BRANDS.contains(&brand) {
+            return Some("image/avif");
+        }
+        heic |= HEIC_BRANDS.contains(&brand);
+    }
+    heic.then_some("image/heic")
+}
@@ -0,0 +1,48 @@
+//! Picks a stored encoding from a request's `Accept` header.
+
+/// Returns the quality value `accept` gives to `content_type`, using the most
+/// specific matching range. A missing header accepts everything.
+pub fn quality(accept: Option<&str>, content_type: &str) -> f32 {
+    let accept = match accept {
+        Some(accept) if !accept.trim().is_empty() => accept,
+        _ => return 1.0,
+    };
+    let (main_type, _) = content_type.split_once('/').unwrap_or((content_type, ""));
+    let mut best: Option<(u8, f32)> = None;
+    for range in accept.split(',') {
+        let mut params = range.split(';').map(str::trim);
+        let media_range = params.next().unwrap_or_default().to_ascii_lowercase();
+        let specificity = if media_range == content_type {
+            3
+        } else if media_range.strip_suffix("/*") == Some(main_type) {
+            2
+        } else if media_range == "*/*" {
+            1
+        } else {
+            continue;
+        };
+        let q = params
+            .filter_map(|p| p.strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0)
+            .clamp(0.0, 1.0);
+        if best.is_none_or(|(s, _)| specificity > s) {
+            best = Some((specificity, q));
+        }
+    }
+    best.map_or(0.0, |(_, q)| q)
+}
+
+/// Chooses among `(content_type, size, item)` candidates: the highest quality
+/// wins and ties go to the smaller object. `None` when nothing is acceptable.
+pub fn choose<'a, T>(
+    accept: Option<&str>,
+    candidates: impl IntoIterator<Item = (T, &'a str, i64)>,
+) -> Option<T> {
+    candidates
+        .into_iter()
+        .map(|(item, content_type, size)| (quality(accept, content_type), size, item))
+        .filter(|(q, _, _)| *q > 0.0)
+        .max_by(|a, b| a.0.total_cmp(&b.0).then(b.1.cmp(&a.1)))
+        .map(|(_, _, item)| item)
+}
@@ -0,0 +1,48 @@
+use sea_orm_migration::{
+    prelude::*,
+    schema::{big_integer, binary, integer, json_binary, string, timestamp_with_time_zone},
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(TusUpload::Table)
+                    .if_not_exists()
+                    .col(string(TusUpload::Id).primary_key())
+                    .col(integer(TusUpload::UserId))
+                    .col(string(TusUpload::S3UploadId))
+                    .col(big_integer(TusUpload::UploadLength))
+                    .col(big_integer(TusUpload::UploadOffset))
+                    .col(json_binary(TusUpload::Parts))
+                    .col(binary(TusUpload::Buffer))
+                    .col(timestamp_with_time_zone(TusUpload::ExpiresAt))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(TusUpload::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TusUpload {
+    Table,
+    Id,
+    UserId,
+    S3UploadId,
+    UploadLength,
+    UploadOffset,
+    Parts,
+    Buffer,
+    ExpiresAt,
+}
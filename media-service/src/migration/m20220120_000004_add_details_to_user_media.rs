@@ -0,0 +1,65 @@
+use sea_orm_migration::{
+    prelude::*,
+    schema::{big_integer_null, integer_null, string_null, text_null, timestamp_with_time_zone},
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserMedia::Table)
+                    .add_column(text_null(UserMedia::Description))
+                    .add_column(text_null(UserMedia::AltText))
+                    .add_column(string_null(UserMedia::OriginalFilename))
+                    .add_column(big_integer_null(UserMedia::SizeBytes))
+                    .add_column(integer_null(UserMedia::Width))
+                    .add_column(integer_null(UserMedia::Height))
+                    .add_column(
+                        timestamp_with_time_zone(UserMedia::CreatedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .add_column(
+                        timestamp_with_time_zone(UserMedia::UpdatedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserMedia::Table)
+                    .drop_column(UserMedia::Description)
+                    .drop_column(UserMedia::AltText)
+                    .drop_column(UserMedia::OriginalFilename)
+                    .drop_column(UserMedia::SizeBytes)
+                    .drop_column(UserMedia::Width)
+                    .drop_column(UserMedia::Height)
+                    .drop_column(UserMedia::CreatedAt)
+                    .drop_column(UserMedia::UpdatedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserMedia {
+    Table,
+    Description,
+    AltText,
+    OriginalFilename,
+    SizeBytes,
+    Width,
+    Height,
+    CreatedAt,
+    UpdatedAt,
+}
@@ -0,0 +1,66 @@
+use sea_orm_migration::{
+    prelude::*,
+    schema::{big_integer, integer, pk_auto, string},
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(MediaRenditions::Table)
+                    .if_not_exists()
+                    .col(pk_auto(MediaRenditions::Id))
+                    .col(integer(MediaRenditions::UserMediaId))
+                    .col(string(MediaRenditions::Name))
+                    .col(string(MediaRenditions::Key))
+                    .col(integer(MediaRenditions::Width))
+                    .col(integer(MediaRenditions::Height))
+                    .col(big_integer(MediaRenditions::SizeBytes))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk_media_renditions_user_media")
+                            .from(MediaRenditions::Table, MediaRenditions::UserMediaId)
+                            .to(UserMedia::Table, UserMedia::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .index(
+                        Index::create()
+                            .name("idx_media_renditions_user_media_name")
+                            .col(MediaRenditions::UserMediaId)
+                            .col(MediaRenditions::Name)
+                            .unique(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(MediaRenditions::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum MediaRenditions {
+    Table,
+    Id,
+    UserMediaId,
+    Name,
+    Key,
+    Width,
+    Height,
+    SizeBytes,
+}
+
+#[derive(DeriveIden)]
+enum UserMedia {
+    Table,
+    Id,
+}
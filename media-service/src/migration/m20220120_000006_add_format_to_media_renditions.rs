@@ -0,0 +1,83 @@
+use sea_orm_migration::{prelude::*, schema::string};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MediaRenditions::Table)
+                    .add_column(string(MediaRenditions::Format).default("image/jpeg"))
+                    .to_owned(),
+            )
+            .await?;
+        // a profile is now stored once per format. 000005 declared the old
+        // index inline, which Postgres keeps as a constraint that DROP INDEX refuses
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "ALTER TABLE media_renditions DROP CONSTRAINT idx_media_renditions_user_media_name",
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_media_renditions_user_media_name_format")
+                    .table(MediaRenditions::Table)
+                    .col(MediaRenditions::UserMediaId)
+                    .col(MediaRenditions::Name)
+                    .col(MediaRenditions::Format)
+                    .unique()
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_media_renditions_user_media_name_format")
+                    .table(MediaRenditions::Table)
+                    .to_owned(),
+            )
+            .await?;
+        // only the JPEG copy of each profile fits the old unique index
+        manager
+            .exec_stmt(
+                Query::delete()
+                    .from_table(MediaRenditions::Table)
+                    .and_where(Expr::col(MediaRenditions::Format).ne("image/jpeg"))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(MediaRenditions::Table)
+                    .drop_column(MediaRenditions::Format)
+                    .to_owned(),
+            )
+            .await?;
+        // back to the constraint 000005 created, so its up runs again
+        manager
+            .get_connection()
+            .execute_unprepared(
+                "ALTER TABLE media_renditions ADD CONSTRAINT idx_media_renditions_user_media_name \
+                 UNIQUE (user_media_id, name)",
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum MediaRenditions {
+    Table,
+    UserMediaId,
+    Name,
+    Format,
+}
@@ -0,0 +1,41 @@
+use sea_orm_migration::{
+    prelude::*,
+    schema::{string_null, text_null},
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserMedia::Table)
+                    .add_column(string_null(UserMedia::FailureCode))
+                    .add_column(text_null(UserMedia::FailureMessage))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(UserMedia::Table)
+                    .drop_column(UserMedia::FailureCode)
+                    .drop_column(UserMedia::FailureMessage)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserMedia {
+    Table,
+    FailureCode,
+    FailureMessage,
+}
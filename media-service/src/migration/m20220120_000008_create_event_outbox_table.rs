@@ -0,0 +1,58 @@
+use sea_orm_migration::{
+    prelude::*,
+    schema::{
+        binary, integer, pk_auto, string, text_null, timestamp_with_time_zone,
+        timestamp_with_time_zone_null,
+    },
+};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(EventOutbox::Table)
+                    .if_not_exists()
+                    .col(pk_auto(EventOutbox::Id))
+                    .col(string(EventOutbox::EventType))
+                    .col(binary(EventOutbox::Payload))
+                    .col(timestamp_with_time_zone(EventOutbox::CreatedAt))
+                    .col(timestamp_with_time_zone_null(EventOutbox::SentAt))
+                    .col(integer(EventOutbox::Attempts).default(0))
+                    .col(text_null(EventOutbox::LastError))
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_event_outbox_sent_at")
+                    .table(EventOutbox::Table)
+                    .col(EventOutbox::SentAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(EventOutbox::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum EventOutbox {
+    Table,
+    Id,
+    EventType,
+    Payload,
+    CreatedAt,
+    SentAt,
+    Attempts,
+    LastError,
+}
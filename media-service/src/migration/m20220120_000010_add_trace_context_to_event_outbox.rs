@@ -0,0 +1,35 @@
+use sea_orm_migration::{prelude::*, schema::json_binary_null};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(EventOutbox::Table)
+                    .add_column(json_binary_null(EventOutbox::TraceContext))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(EventOutbox::Table)
+                    .drop_column(EventOutbox::TraceContext)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum EventOutbox {
+    Table,
+    TraceContext,
+}
@@ -0,0 +1,35 @@
+use sea_orm_migration::{prelude::*, schema::timestamp_with_time_zone_null};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TusUpload::Table)
+                    .add_column(timestamp_with_time_zone_null(TusUpload::ClaimedUntil))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TusUpload::Table)
+                    .drop_column(TusUpload::ClaimedUntil)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TusUpload {
+    Table,
+    ClaimedUntil,
+}
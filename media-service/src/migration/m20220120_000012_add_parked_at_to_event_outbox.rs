@@ -0,0 +1,35 @@
+use sea_orm_migration::{prelude::*, schema::timestamp_with_time_zone_null};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(EventOutbox::Table)
+                    .add_column(timestamp_with_time_zone_null(EventOutbox::ParkedAt))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(EventOutbox::Table)
+                    .drop_column(EventOutbox::ParkedAt)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum EventOutbox {
+    Table,
+    ParkedAt,
+}
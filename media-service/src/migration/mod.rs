@@ -2,12 +2,36 @@ use sea_orm::prelude::async_trait;
 use sea_orm_migration::{MigrationTrait, MigratorTrait};
 
 mod m20220120_000001_create_post_table;
+mod m20220120_000002_create_tus_upload_table;
+mod m20220120_000003_add_mime_type_to_user_media;
+mod m20220120_000004_add_details_to_user_media;
+mod m20220120_000005_create_media_renditions_table;
+mod m20220120_000006_add_format_to_media_renditions;
+mod m20220120_000007_add_failure_to_user_media;
+mod m20220120_000008_create_event_outbox_table;
+mod m20220120_000009_create_processed_events_table;
+mod m20220120_000010_add_trace_context_to_event_outbox;
+mod m20220120_000011_add_claim_to_tus_upload;
+mod m20220120_000012_add_parked_at_to_event_outbox;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20220120_000001_create_post_table::Migration)]
+        vec![
+            Box::new(m20220120_000001_create_post_table::Migration),
+            Box::new(m20220120_000002_create_tus_upload_table::Migration),
+            Box::new(m20220120_000003_add_mime_type_to_user_media::Migration),
+            Box::new(m20220120_000004_add_details_to_user_media::Migration),
+            Box::new(m20220120_000005_create_media_renditions_table::Migration),
+            Box::new(m20220120_000006_add_format_to_media_renditions::Migration),
+            Box::new(m20220120_000007_add_failure_to_user_media::Migration),
+            Box::new(m20220120_000008_create_event_outbox_table::Migration),
+            Box::new(m20220120_000009_create_processed_events_table::Migration),
+            Box::new(m20220120_000010_add_trace_context_to_event_outbox::Migration),
+            Box::new(m20220120_000011_add_claim_to_tus_upload::Migration),
+            Box::new(m20220120_000012_add_parked_at_to_event_outbox::Migration),
+        ]
     }
 }
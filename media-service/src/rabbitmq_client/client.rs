@@ -1,160 +1,83 @@
-use amqprs::{
-    callbacks::{DefaultChannelCallback, DefaultConnectionCallback},
-    channel::{
-        BasicAckArguments, BasicConsumeArguments, BasicNackArguments, BasicPublishArguments,
-        BasicRejectArguments, Channel, ExchangeDeclareArguments, QueueBindArguments,
-        QueueDeclareArguments,
-    },
-    connection::{self, Connection, OpenConnectionArguments},
-    consumer::{AsyncConsumer, DefaultConsumer},
-    BasicProperties, Deliver,
-};
-use core::fmt;
-use sea_orm::DatabaseConnection;
-use serde::{Deserialize, Serialize};
+use amqprs::FieldTable;
+use media_amqp::{ConnectionState, PublishError, RabbitmqConfig, Supervisor, Topology};
+use media_events::Event;
 use std::{
-    f32::consts::E,
-    fmt::{Debug, Formatter},
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
 };
 
-use crate::{rabbitmq_client::models::MediaUploadedMessage, service};
+use crate::rabbitmq_client::models::PRODUCER;
 #[derive(Clone)]
 pub struct RabbitmqClient {
-    pub connection: Connection,
-    channel: Channel,
+    supervisor: Arc<Supervisor>,
     config: RabbitmqConfig,
 }
 
 impl Debug for RabbitmqClient {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("RabbitmqClient")
-            .field("connection", &self.channel.to_string())
-            .field("channel", &self.channel.to_string())
+            .field("state", &self.supervisor.state())
             .field("config", &self.config)
             .finish()
     }
 }
-#[derive(Clone, Debug)]
-pub struct RabbitmqConfig {
-    pub host: String,
-    pub port: u16,
-    pub username: String,
-    pub password: String,
-
-    pub exchange: String,
-}
-#[derive(Debug)]
-pub struct RabbitError(String);
 impl RabbitmqClient {
-    pub async fn new(config: RabbitmqConfig) -> Result<Self, RabbitError> {
-        let connection = Connection::open(&OpenConnectionArguments::new(
-            &config.host,
-            config.port,
-            &config.username,
-            &config.password,
-        ))
-        .await;
-        let connection = match connection {
-            Ok(c) => c,
-            Err(e) => return Err(RabbitError(e.to_string())),
-        };
-        // open a channel on the connection
-        let channel = connection.open_channel(None).await.unwrap();
-
-        let channel_result = channel
-            .exchange_declare(
-                ExchangeDeclareArguments::default()
-                    .exchange("media_events".to_owned())
-                    .exchange_type("topic".to_owned())
-                    .finish(),
-            )
-            .await;
-        match channel_result {
-            Ok(c) => {}
-            Err(e) => return Err(RabbitError(e.to_string())),
+    /// Creates a client that stays disconnected until `supervise` is called.
+    pub fn new(config: RabbitmqConfig) -> Self {
+        Self {
+            supervisor: Arc::new(Supervisor::new(config.clone())),
+            config,
         }
-        return Ok(Self {
-            connection: connection,
-            channel: channel,
-            config: config,
-        });
     }
-    pub async fn send_message(&self, message: impl Into<Vec<u8>>) -> Result<(), RabbitError> {
-        // create arguments for basic_publish
-        let args = BasicPublishArguments::new(&self.config.exchange, "media.uploaded");
 
-        self.channel
-            .basic_publish(BasicProperties::default(), message.into(), args)
-            .await
-            .map_err(|e| return RabbitError(e.to_string()))
+    /// Connects in the background and keeps reconnecting whenever the
+    /// connection drops, declaring `topology` again each time.
+    pub fn supervise(&self, topology: Topology) {
+        let supervisor = self.supervisor.clone();
+        tokio::spawn(async move { supervisor.run(topology).await });
     }
-}
 
-pub struct CustomConsumer {
-    pub db_conn: DatabaseConnection,
-}
-impl AsyncConsumer for CustomConsumer {
-    fn consume<'life0, 'life1, 'async_trait>(
-        &'life0 mut self,
-        channel: &'life1 Channel,
-        deliver: Deliver,
-        _basic_properties: BasicProperties,
-        content: Vec<u8>,
-    ) -> ::core::pin::Pin<
-        Box<dyn ::core::future::Future<Output = ()> + ::core::marker::Send + 'async_trait>,
-    >
-    where
-        'life0: 'async_trait,
-        'life1: 'async_trait,
-        Self: 'async_trait,
-    {
-        Box::pin(async move {
-            match deliver.routing_key().as_str() {
-                "media.compressed" => {
-                    // Process the message content
-                    let msg: Result<MediaCompressedMessage, serde_json::Error> =
-                        serde_json::from_slice(&content);
-                    match msg {
-                        Ok(m) => {
-                            let _ = service::Mutation::update_user_media_by_id(
-                                &self.db_conn,
-                                &m.id,
-                                "compressed".to_string(),
-                            )
-                            .await;
-                            println!("{:?}", m);
-                        }
-                        Err(e) => {
-                            println!("{:?}", e);
-                        }
-                    };
-                }
-                _ => {
-                    println!("Received message: {:?}", String::from_utf8(content));
-                }
-            }
-            if let Err(err) = channel
-                .basic_ack(BasicAckArguments::new(deliver.delivery_tag(), false))
-                .await
-            {
-                eprintln!("Failed to ack message: {:?}", err);
-            }
-        })
+    pub fn state(&self) -> ConnectionState {
+        self.supervisor.state()
     }
-}
 
-enum MessageKey {
-    MediaCompressed(String),
-}
-#[derive(Deserialize, Serialize, Debug)]
-pub struct MediaCompressedMessage {
-    pub id: String,
-    pub compressed_id: String,
-    pub status: String,
-}
+    /// Publishes `message` under its event type and waits until the broker
+    /// has confirmed it was routed to a queue and stored.
+    pub async fn send_message<E: Event>(&self, message: E) -> Result<(), PublishError> {
+        let content = media_events::encode(PRODUCER, message).map_err(PublishError::Encode)?;
+        self.send_encoded(E::TYPE, content).await
+    }
+
+    /// Like `send_message`, for an event that was encoded earlier.
+    pub async fn send_encoded(
+        &self,
+        routing_key: &str,
+        content: Vec<u8>,
+    ) -> Result<(), PublishError> {
+        let channel = self
+            .supervisor
+            .channel()
+            .await
+            .ok_or(PublishError::NotConnected)?;
+        channel
+            .publish(&self.config.exchange, routing_key, content)
+            .await
+    }
 
-impl Into<Vec<u8>> for MediaCompressedMessage {
-    fn into(self) -> Vec<u8> {
-        serde_json::to_vec(&self).unwrap()
+    /// Publishes `content` straight to `queue` through the default exchange.
+    pub async fn send_to_queue(
+        &self,
+        queue: &str,
+        headers: FieldTable,
+        content: Vec<u8>,
+    ) -> Result<(), PublishError> {
+        let channel = self
+            .supervisor
+            .channel()
+            .await
+            .ok_or(PublishError::NotConnected)?;
+        channel
+            .publish_with_headers("", queue, headers, content)
+            .await
     }
 }
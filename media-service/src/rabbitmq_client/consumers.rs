@@ -0,0 +1,313 @@
+use std::{sync::Arc, time::Duration};
+
+use amqprs::{FieldTable, FieldValue};
+use aws_sdk_s3::Client;
+use chrono::Utc;
+use media_dispatch::{Delivery, DispatchMetrics, Dispatcher, Outcome};
+use media_events::Envelope;
+use sea_orm::{
+    prelude::Uuid, ConnectionTrait, DatabaseConnection, DbErr, RuntimeErr, SqlxError,
+    TransactionTrait,
+};
+use tokio::{sync::broadcast, time::sleep};
+use tracing::{error, info, warn};
+
+use crate::{
+    entity::media_renditions,
+    handlers::handlers::MEDIA_BUCKET,
+    rabbitmq_client::{
+        client::RabbitmqClient,
+        models::{
+            MediaCompressedMessage, MediaCompressionFailedMessage, MediaDeletedMessage,
+            MediaStatusChangedMessage, Rendition,
+        },
+    },
+    service,
+};
+
+/// Name the results consumer records its events under in `processed_events`.
+const LEDGER_CONSUMER: &str = "media_service";
+/// How long processed event ids are remembered. Far longer than any
+/// redelivery or retry takes to arrive.
+const LEDGER_RETENTION: chrono::Duration = chrono::Duration::days(30);
+/// Deliveries that can't be applied, kept for inspection instead of cycling
+/// through the `media_service` queue.
+pub const DEAD_LETTER_QUEUE: &str = "media_service.dlq";
+const ERROR_MESSAGE_HEADER: &str = "x-error-message";
+/// Pause before a delivery that failed for a passing reason, like a lost
+/// database connection, goes back on the queue.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Handlers for the shared `media_service` queue, which the compression
+/// service reports back on and deletions are cleaned up from.
+pub fn compression_results(
+    metrics: Arc<DispatchMetrics>,
+    db_conn: DatabaseConnection,
+    rabbitmq_client: RabbitmqClient,
+    s3_client: Client,
+) -> Dispatcher {
+    let failed_db = db_conn.clone();
+    let failed_client = rabbitmq_client.clone();
+    let deleted_client = rabbitmq_client.clone();
+    Dispatcher::new(metrics)
+        .on(move |envelope, delivery| {
+            media_deleted(
+                s3_client.clone(),
+                deleted_client.clone(),
+                envelope,
+                delivery,
+            )
+        })
+        .on(move |envelope, delivery| {
+            media_compressed(db_conn.clone(), rabbitmq_client.clone(), envelope, delivery)
+        })
+        .on(move |envelope, delivery| {
+            media_compression_failed(failed_db.clone(), failed_client.clone(), envelope, delivery)
+        })
+}
+
+/// Feeds status changes from this replica's exclusive queue into the
+/// in-process channel that event stream handlers subscribe to.
+pub fn status_changes(
+    metrics: Arc<DispatchMetrics>,
+    status_events: broadcast::Sender<MediaStatusChangedMessage>,
+) -> Dispatcher {
+    Dispatcher::new(metrics).on(move |envelope: Envelope<MediaStatusChangedMessage>, _| {
+        // sending only fails when nobody is listening, which is fine
+        let _ = status_events.send(envelope.data);
+        async { Outcome::Ack }
+    })
+}
+
+async fn media_compressed(
+    db_conn: DatabaseConnection,
+    rabbitmq_client: RabbitmqClient,
+    Envelope {
+        event_id, data: m, ..
+    }: Envelope<MediaCompressedMessage>,
+    delivery: Delivery,
+) -> Outcome {
+    let applied = async {
+        let txn = db_conn.begin().await?;
+        if !first_delivery(&txn, event_id).await? {
+            return Ok(None);
+        }
+        let media =
+            service::Mutation::update_user_media_by_id(&txn, &m.id, m.status.clone()).await?;
+        let renditions = m.renditions.iter().cloned().map(Into::into).collect();
+        service::Mutation::replace_media_renditions(&txn, media.id, renditions).await?;
+        if let (Some(width), Some(height)) = (m.width, m.height) {
+            service::Mutation::set_user_media_dimensions(&txn, &m.id, width as i32, height as i32)
+                .await?;
+        }
+        txn.commit().await?;
+        Ok::<_, DbErr>(Some(media))
+    };
+    let media = match applied.await {
+        Ok(Some(media)) => media,
+        Ok(None) => return duplicate(event_id),
+        Err(e) => return db_failure(&rabbitmq_client, delivery, &m.id, e).await,
+    };
+    if let Err(e) = rabbitmq_client
+        .send_message(MediaStatusChangedMessage::from(&media))
+        .await
+    {
+        warn!("failed to publish status change: {:?}", e);
+    }
+    Outcome::Ack
+}
+
+async fn media_compression_failed(
+    db_conn: DatabaseConnection,
+    rabbitmq_client: RabbitmqClient,
+    Envelope {
+        event_id, data: m, ..
+    }: Envelope<MediaCompressionFailedMessage>,
+    delivery: Delivery,
+) -> Outcome {
+    warn!("compression of {} failed: {} {}", m.id, m.code, m.message);
+    let applied = async {
+        let txn = db_conn.begin().await?;
+        if !first_delivery(&txn, event_id).await? {
+            return Ok(None);
+        }
+        let media = service::Mutation::mark_user_media_failed(
+            &txn,
+            &m.id,
+            m.code.clone(),
+            m.message.clone(),
+        )
+        .await?;
+        txn.commit().await?;
+        Ok::<_, DbErr>(Some(media))
+    };
+    let media = match applied.await {
+        Ok(Some(media)) => media,
+        Ok(None) => return duplicate(event_id),
+        Err(e) => return db_failure(&rabbitmq_client, delivery, &m.id, e).await,
+    };
+    if let Err(e) = rabbitmq_client
+        .send_message(MediaStatusChangedMessage::from(&media))
+        .await
+    {
+        warn!("failed to publish status change: {:?}", e);
+    }
+    Outcome::Ack
+}
+
+/// Removes the objects of media whose row is already gone: the original, the
+/// legacy compressed copy and everything under the compressed id, like the
+/// renditions and the compression service's manifest. Deleting a missing key
+/// succeeds, so redeliveries are harmless.
+async fn media_deleted(
+    s3_client: Client,
+    rabbitmq_client: RabbitmqClient,
+    Envelope { data: m, .. }: Envelope<MediaDeletedMessage>,
+    delivery: Delivery,
+) -> Outcome {
+    match delete_objects(&s3_client, &m).await {
+        Ok(()) => Outcome::Ack,
+        Err(e) => {
+            error!("failed to delete objects of {}: {}", m.id, e);
+            retry_once(&rabbitmq_client, delivery, e).await
+        }
+    }
+}
+
+async fn delete_objects(s3_client: &Client, m: &MediaDeletedMessage) -> Result<(), String> {
+    let mut keys = vec![m.id.clone(), m.compressed_id.clone()];
+    let listed = s3_client
+        .list_objects_v2()
+        .bucket(MEDIA_BUCKET)
+        .prefix(format!("{}/", m.compressed_id))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    keys.extend(
+        listed
+            .contents()
+            .iter()
+            .filter_map(|object| object.key().map(str::to_owned)),
+    );
+    for key in keys {
+        s3_client
+            .delete_object()
+            .bucket(MEDIA_BUCKET)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Records `event_id` in the ledger, returning `false` if it was already
+/// applied. Events from before envelopes carry no id and are always applied.
+async fn first_delivery<C: ConnectionTrait>(db: &C, event_id: Uuid) -> Result<bool, DbErr> {
+    if event_id.is_nil() {
+        return Ok(true);
+    }
+    service::Mutation::record_processed_event(db, LEDGER_CONSUMER, event_id).await
+}
+
+fn duplicate(event_id: Uuid) -> Outcome {
+    info!("skipping already processed event {}", event_id);
+    Outcome::Ack
+}
+
+/// Drops ledger entries past `LEDGER_RETENTION` once an hour.
+pub async fn prune_ledger(db_conn: DatabaseConnection) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        let before = Utc::now() - LEDGER_RETENTION;
+        if let Err(e) = service::Mutation::delete_processed_events(&db_conn, before).await {
+            error!("failed to prune processed events: {:?}", e);
+        }
+    }
+}
+
+/// Media deleted before its result arrived has nothing left to update. A
+/// lost database connection gets the delivery one more try after a pause;
+/// anything else, like a constraint the result violates, won't go away by
+/// redelivering, so the delivery is parked in the dead letter queue.
+async fn db_failure(
+    rabbitmq_client: &RabbitmqClient,
+    delivery: Delivery,
+    media_id: &str,
+    e: DbErr,
+) -> Outcome {
+    error!("failed to record result for {}: {:?}", media_id, e);
+    if let DbErr::RecordNotFound(_) = e {
+        return Outcome::Ack;
+    }
+    if connection_lost(&e) {
+        return retry_once(rabbitmq_client, delivery, e.to_string()).await;
+    }
+    park(rabbitmq_client, delivery, e.to_string()).await
+}
+
+/// Puts `delivery` back on the queue after a pause the first time it fails,
+/// and parks it once the retry failed as well.
+async fn retry_once(
+    rabbitmq_client: &RabbitmqClient,
+    delivery: Delivery,
+    reason: String,
+) -> Outcome {
+    if delivery.redelivered {
+        return park(rabbitmq_client, delivery, reason).await;
+    }
+    sleep(RETRY_DELAY).await;
+    Outcome::Requeue
+}
+
+/// Moves `delivery` to the dead letter queue along with why it failed.
+async fn park(rabbitmq_client: &RabbitmqClient, delivery: Delivery, reason: String) -> Outcome {
+    let mut headers = FieldTable::new();
+    headers.insert(
+        ERROR_MESSAGE_HEADER.try_into().unwrap(),
+        FieldValue::from(reason.as_str()),
+    );
+    match rabbitmq_client
+        .send_to_queue(DEAD_LETTER_QUEUE, headers, delivery.content)
+        .await
+    {
+        Ok(()) => Outcome::Ack,
+        Err(e) => {
+            error!(
+                "failed to dead letter {} delivery: {}",
+                delivery.routing_key, e
+            );
+            Outcome::Requeue
+        }
+    }
+}
+
+/// Whether `e` came from losing the database rather than from the query itself.
+fn connection_lost(e: &DbErr) -> bool {
+    match e {
+        DbErr::Conn(_) | DbErr::ConnectionAcquire(_) => true,
+        DbErr::Exec(RuntimeErr::SqlxError(e)) | DbErr::Query(RuntimeErr::SqlxError(e)) => {
+            matches!(
+                e,
+                SqlxError::Io(_) | SqlxError::PoolTimedOut | SqlxError::PoolClosed
+            )
+        }
+        _ => false,
+    }
+}
+
+impl From<Rendition> for media_renditions::Model {
+    fn from(rendition: Rendition) -> Self {
+        Self {
+            id: 0,
+            user_media_id: 0,
+            name: rendition.name,
+            format: rendition.format,
+            key: rendition.key,
+            width: rendition.width as i32,
+            height: rendition.height as i32,
+            size_bytes: rendition.size,
+        }
+    }
+}
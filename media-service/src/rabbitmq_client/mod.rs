@@ -1,2 +1,4 @@
 pub mod client;
+pub mod consumers;
 pub mod models;
+pub mod outbox;
@@ -1,26 +1,19 @@
-use serde::{ser::SerializeStruct, Deserialize, Serialize};
-use uuid::Uuid;
+pub use media_events::{
+    MediaCompressedMessage, MediaCompressionFailedMessage, MediaDeletedMessage,
+    MediaStatusChangedMessage, MediaUploadedMessage, Rendition, MEDIA_STATUS_CHANGED,
+};
 
-#[derive(Deserialize, Serialize, Debug)]
-pub struct MediaUploadedMessage {
-    pub id: String,
-    pub compressed_id: String,
-}
+use crate::entity::user_media;
 
-// impl Serialize for MediaUploadedMessage {
-//     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-//     where
-//         S: serde::Serializer,
-//     {
-//         let mut state = serializer.serialize_struct("MediaUploadedMessage", 2)?;
-//         state.serialize_field("id", &self.id.to_string())?;
-//         state.serialize_field("compressed_id", &self.compressed_id.to_string())?;
-//         state.end()
-//     }
-// }
+/// The service name recorded as `producer` on every event media-service publishes.
+pub const PRODUCER: &str = "media-service";
 
-impl Into<Vec<u8>> for MediaUploadedMessage {
-    fn into(self) -> Vec<u8> {
-        serde_json::to_vec(&self).unwrap()
+impl From<&user_media::Model> for MediaStatusChangedMessage {
+    fn from(media: &user_media::Model) -> Self {
+        Self {
+            media_id: media.media_id.clone(),
+            user_id: media.user_id,
+            status: media.status.clone(),
+        }
     }
 }
@@ -0,0 +1,133 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use media_amqp::{ConnectionState, PublishError};
+use sea_orm::{DatabaseConnection, DbErr, TransactionTrait};
+use tokio::sync::Notify;
+use tracing::{error, info_span, warn, Instrument};
+
+use crate::{rabbitmq_client::client::RabbitmqClient, service};
+
+/// Rows published per transaction.
+const BATCH_SIZE: u64 = 100;
+/// How often to look for events when nobody wakes the relay, e.g. after a
+/// crash left rows behind or while the broker was down.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How long confirmed events are kept around for inspection.
+const RETENTION: chrono::Duration = chrono::Duration::days(7);
+/// Nacks an event may collect before it is parked.
+const MAX_ATTEMPTS: i32 = 20;
+
+/// Publishes events from `event_outbox` in the order they were written and
+/// marks each one sent once the broker confirms it. Rows stay pending until
+/// then, so an event can go out more than once but is never lost. Rows that
+/// can never be delivered are parked instead, see [`should_park`].
+pub async fn relay(
+    db_conn: DatabaseConnection,
+    rabbitmq_client: RabbitmqClient,
+    wake: Arc<Notify>,
+) {
+    loop {
+        match relay_batch(&db_conn, &rabbitmq_client).await {
+            // a full batch likely means more are waiting
+            Ok(sent) if sent as u64 == BATCH_SIZE => continue,
+            Ok(_) => {}
+            Err(e) => error!("outbox relay failed: {:?}", e),
+        }
+        if let Err(e) =
+            service::Mutation::delete_sent_events(&db_conn, Utc::now() - RETENTION).await
+        {
+            error!("failed to prune outbox: {:?}", e);
+        }
+        tokio::select! {
+            _ = wake.notified() => {}
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    }
+}
+
+/// Publishes one batch of pending events and returns how many went out.
+async fn relay_batch(
+    db_conn: &DatabaseConnection,
+    rabbitmq_client: &RabbitmqClient,
+) -> Result<usize, DbErr> {
+    if rabbitmq_client.state() != ConnectionState::Connected {
+        return Ok(0);
+    }
+    let txn = db_conn.begin().await?;
+    let events = service::Query::lock_pending_events(&txn, BATCH_SIZE).await?;
+    let mut sent = 0;
+    for event in events {
+        let span = info_span!(
+            "relay",
+            outbox.id = event.id,
+            outbox.attempts = event.attempts
+        );
+        let saved = event
+            .trace_context
+            .and_then(|saved| serde_json::from_value::<HashMap<String, String>>(saved).ok());
+        if let Some(saved) = saved {
+            media_telemetry::continue_from_saved(&span, &saved);
+        }
+        match rabbitmq_client
+            .send_encoded(&event.event_type, event.payload)
+            .instrument(span)
+            .await
+        {
+            Ok(()) => {
+                service::Mutation::mark_event_sent(&txn, event.id).await?;
+                sent += 1;
+            }
+            Err(e) if should_park(event.attempts + 1, &e) => {
+                error!(
+                    "parking outbox event {} ({}): {}",
+                    event.id, event.event_type, e
+                );
+                service::Mutation::park_event(&txn, event.id, e.to_string()).await?;
+            }
+            // stop here so later events don't overtake this one
+            Err(e) => {
+                warn!("failed to publish outbox event {}: {}", event.id, e);
+                service::Mutation::record_event_failure(&txn, event.id, e.to_string()).await?;
+                break;
+            }
+        }
+    }
+    txn.commit().await?;
+    Ok(sent)
+}
+
+/// Whether to give up on an event after its `attempts`th failed publish.
+/// Events nobody is bound for or that can't be encoded never go out, and
+/// one the broker keeps refusing is given `MAX_ATTEMPTS`. Connection trouble
+/// never parks anything, the event goes out once the broker is back.
+fn should_park(attempts: i32, error: &PublishError) -> bool {
+    match error {
+        PublishError::Unroutable(_) | PublishError::Encode(_) => true,
+        PublishError::Nacked => attempts >= MAX_ATTEMPTS,
+        PublishError::NotConnected | PublishError::Channel(_) | PublishError::Unconfirmed => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parks_unroutable_events_right_away() {
+        let error = PublishError::Unroutable("NO_ROUTE".to_string());
+        assert!(should_park(1, &error));
+    }
+
+    #[test]
+    fn parks_nacked_events_once_out_of_attempts() {
+        assert!(!should_park(MAX_ATTEMPTS - 1, &PublishError::Nacked));
+        assert!(should_park(MAX_ATTEMPTS, &PublishError::Nacked));
+    }
+
+    #[test]
+    fn keeps_retrying_while_the_broker_is_unreachable() {
+        assert!(!should_park(MAX_ATTEMPTS * 10, &PublishError::NotConnected));
+        assert!(!should_park(MAX_ATTEMPTS * 10, &PublishError::Unconfirmed));
+    }
+}
@@ -1,11 +1,25 @@
-use crate::entity::{user_media, user_media::Entity as UserMedia};
-use sea_orm::*;
+use crate::{
+    entity::{
+        event_outbox, event_outbox::Entity as EventOutbox, media_renditions,
+        media_renditions::Entity as MediaRenditions, processed_events,
+        processed_events::Entity as ProcessedEvents, tus_upload, tus_upload::Entity as TusUpload,
+        user_media, user_media::Entity as UserMedia,
+    },
+    rabbitmq_client::models::PRODUCER,
+};
+use chrono::{DateTime, Utc};
+use media_events::Event;
+use sea_orm::{
+    prelude::Uuid,
+    sea_query::{Expr, OnConflict},
+    *,
+};
 
 pub struct Mutation;
 
 impl Mutation {
-    pub async fn create_post(
-        db: &DbConn,
+    pub async fn create_post<C: ConnectionTrait>(
+        db: &C,
         form_data: user_media::Model,
     ) -> Result<user_media::ActiveModel, DbErr> {
         user_media::ActiveModel {
@@ -13,46 +27,363 @@ impl Mutation {
             media_id: Set(form_data.media_id),
             media_compressed_id: Set(form_data.media_compressed_id),
             status: Set(form_data.status),
+            mime_type: Set(form_data.mime_type),
+            description: Set(form_data.description),
+            alt_text: Set(form_data.alt_text),
+            original_filename: Set(form_data.original_filename),
+            size_bytes: Set(form_data.size_bytes),
+            width: Set(form_data.width),
+            height: Set(form_data.height),
             ..Default::default()
         }
         .save(db)
         .await
     }
 
-    pub async fn update_user_media_by_id(
-        db: &DbConn,
+    pub async fn update_user_media_by_id<C: ConnectionTrait>(
+        db: &C,
         media_id: &str,
         status: String,
     ) -> Result<user_media::Model, DbErr> {
         let user_media = UserMedia::find()
-            .filter(user_media::Column::MediaId.contains(media_id))
+            .filter(user_media::Column::MediaId.eq(media_id))
             .one(db)
             .await?;
         match user_media {
             Some(user_media) => {
-                user_media::ActiveModel {
-                    id: Set(user_media.id),
-                    user_id: Set(user_media.user_id),
-                    media_id: Set(user_media.media_id),
-                    media_compressed_id: Set(user_media.media_compressed_id),
-                    status: Set(status),
-                }
-                .update(db)
-                .await
+                let mut user_media: user_media::ActiveModel = user_media.into();
+                user_media.status = Set(status);
+                // a reprocessed upload no longer carries the earlier failure
+                user_media.failure_code = Set(None);
+                user_media.failure_message = Set(None);
+                user_media.update(db).await
             }
-            None => Err(sea_orm::DbErr::ConvertFromU64("none user media")),
+            None => Err(DbErr::RecordNotFound(media_id.to_owned())),
         }
     }
 
-    // pub async fn delete_post(db: &DbConn, id: i32) -> Result<DeleteResult, DbErr> {
-    //     let post: user_media::ActiveModel = Post::find_by_id(id)
-    //         .one(db)
-    //         .await?
-    //         .ok_or(DbErr::Custom("Cannot find post.".to_owned()))
-    //         .map(Into::into)?;
+    /// Moves an upload from `pending` to `created`. Returns `None` if it was
+    /// no longer pending, e.g. because a concurrent request completed it first.
+    pub async fn start_pending_user_media<C: ConnectionTrait>(
+        db: &C,
+        media_id: &str,
+    ) -> Result<Option<user_media::Model>, DbErr> {
+        let updated = UserMedia::update_many()
+            .col_expr(user_media::Column::Status, Expr::value("created"))
+            .col_expr(user_media::Column::UpdatedAt, Expr::value(Utc::now()))
+            .filter(user_media::Column::MediaId.eq(media_id))
+            .filter(user_media::Column::Status.eq("pending"))
+            .exec_with_returning(db)
+            .await?;
+        Ok(updated.into_iter().next())
+    }
+
+    /// Deletes a media row only while it is still `pending`, so an upload
+    /// completed in the meantime is kept. Returns whether the row was deleted.
+    pub async fn delete_pending_user_media<C: ConnectionTrait>(
+        db: &C,
+        id: i32,
+    ) -> Result<bool, DbErr> {
+        let deleted = UserMedia::delete_many()
+            .filter(user_media::Column::Id.eq(id))
+            .filter(user_media::Column::Status.eq("pending"))
+            .exec(db)
+            .await?;
+        Ok(deleted.rows_affected > 0)
+    }
 
-    //     post.delete(db).await
-    // }
+    pub async fn mark_user_media_failed<C: ConnectionTrait>(
+        db: &C,
+        media_id: &str,
+        code: String,
+        message: String,
+    ) -> Result<user_media::Model, DbErr> {
+        let user_media = UserMedia::find()
+            .filter(user_media::Column::MediaId.eq(media_id))
+            .one(db)
+            .await?
+            .ok_or(DbErr::RecordNotFound(media_id.to_owned()))?;
+        let mut user_media: user_media::ActiveModel = user_media.into();
+        user_media.status = Set("failed".to_string());
+        user_media.failure_code = Set(Some(code));
+        user_media.failure_message = Set(Some(message));
+        user_media.update(db).await
+    }
+
+    pub async fn update_user_media_details(
+        db: &DbConn,
+        user_media: user_media::Model,
+        description: Option<String>,
+        alt_text: Option<String>,
+    ) -> Result<user_media::Model, DbErr> {
+        let mut user_media: user_media::ActiveModel = user_media.into();
+        user_media.description = Set(description);
+        user_media.alt_text = Set(alt_text);
+        user_media.update(db).await
+    }
+
+    pub async fn set_user_media_dimensions<C: ConnectionTrait>(
+        db: &C,
+        media_id: &str,
+        width: i32,
+        height: i32,
+    ) -> Result<UpdateResult, DbErr> {
+        UserMedia::update_many()
+            .col_expr(user_media::Column::Width, Expr::value(width))
+            .col_expr(user_media::Column::Height, Expr::value(height))
+            // update_many skips before_save, which keeps this current elsewhere
+            .col_expr(user_media::Column::UpdatedAt, Expr::value(Utc::now()))
+            .filter(user_media::Column::MediaId.eq(media_id))
+            .exec(db)
+            .await
+    }
+
+    pub async fn delete_user_media<C: ConnectionTrait>(
+        db: &C,
+        id: i32,
+    ) -> Result<DeleteResult, DbErr> {
+        UserMedia::delete_by_id(id).exec(db).await
+    }
+
+    /// Swaps the stored renditions of a media item for `renditions`, so a
+    /// redelivered completion event leaves a single set behind.
+    pub async fn replace_media_renditions<C: TransactionTrait>(
+        db: &C,
+        user_media_id: i32,
+        renditions: Vec<media_renditions::Model>,
+    ) -> Result<(), DbErr> {
+        let txn = db.begin().await?;
+        MediaRenditions::delete_many()
+            .filter(media_renditions::Column::UserMediaId.eq(user_media_id))
+            .exec(&txn)
+            .await?;
+        if !renditions.is_empty() {
+            MediaRenditions::insert_many(renditions.into_iter().map(|rendition| {
+                media_renditions::ActiveModel {
+                    user_media_id: Set(user_media_id),
+                    name: Set(rendition.name),
+                    format: Set(rendition.format),
+                    key: Set(rendition.key),
+                    width: Set(rendition.width),
+                    height: Set(rendition.height),
+                    size_bytes: Set(rendition.size_bytes),
+                    ..Default::default()
+                }
+            }))
+            .exec(&txn)
+            .await?;
+        }
+        txn.commit().await
+    }
+
+    pub async fn create_tus_upload<C: ConnectionTrait>(
+        db: &C,
+        form_data: tus_upload::Model,
+    ) -> Result<tus_upload::Model, DbErr> {
+        tus_upload::ActiveModel {
+            id: Set(form_data.id),
+            user_id: Set(form_data.user_id),
+            s3_upload_id: Set(form_data.s3_upload_id),
+            upload_length: Set(form_data.upload_length),
+            upload_offset: Set(form_data.upload_offset),
+            parts: Set(form_data.parts),
+            buffer: Set(form_data.buffer),
+            expires_at: Set(form_data.expires_at),
+            claimed_until: Set(form_data.claimed_until),
+        }
+        .insert(db)
+        .await
+    }
+
+    /// Records what a PATCH wrote and ends its claim.
+    pub async fn update_tus_upload_progress<C: ConnectionTrait>(
+        db: &C,
+        id: &str,
+        upload_offset: i64,
+        parts: tus_upload::UploadedParts,
+        buffer: Vec<u8>,
+    ) -> Result<tus_upload::Model, DbErr> {
+        tus_upload::ActiveModel {
+            id: Set(id.to_owned()),
+            upload_offset: Set(upload_offset),
+            parts: Set(parts),
+            buffer: Set(buffer),
+            claimed_until: Set(None),
+            ..Default::default()
+        }
+        .update(db)
+        .await
+    }
+
+    /// Claims the upload for one writer until `until`. `None` if it's gone or
+    /// another writer's claim hasn't run out by `now`.
+    pub async fn claim_tus_upload(
+        db: &DbConn,
+        id: &str,
+        now: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<Option<tus_upload::Model>, DbErr> {
+        let claimed = TusUpload::update_many()
+            .col_expr(tus_upload::Column::ClaimedUntil, Expr::value(until))
+            .filter(tus_upload::Column::Id.eq(id))
+            .filter(
+                Condition::any()
+                    .add(tus_upload::Column::ClaimedUntil.is_null())
+                    .add(tus_upload::Column::ClaimedUntil.lt(now)),
+            )
+            .exec_with_returning(db)
+            .await?;
+        Ok(claimed.into_iter().next())
+    }
+
+    /// Extends the claim of a writer that is still receiving the body.
+    pub async fn renew_tus_upload_claim(
+        db: &DbConn,
+        id: &str,
+        until: DateTime<Utc>,
+    ) -> Result<(), DbErr> {
+        TusUpload::update_many()
+            .col_expr(tus_upload::Column::ClaimedUntil, Expr::value(until))
+            .filter(tus_upload::Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn release_tus_upload(db: &DbConn, id: &str) -> Result<(), DbErr> {
+        TusUpload::update_many()
+            .col_expr(
+                tus_upload::Column::ClaimedUntil,
+                Expr::value(Option::<DateTime<Utc>>::None),
+            )
+            .filter(tus_upload::Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_tus_upload<C: ConnectionTrait>(
+        db: &C,
+        id: &str,
+    ) -> Result<DeleteResult, DbErr> {
+        TusUpload::delete_by_id(id).exec(db).await
+    }
+
+    /// Records `event` for the outbox relay. Pass the transaction that makes
+    /// the change the event announces, so both commit or neither does.
+    pub async fn enqueue_event<C: ConnectionTrait, E: Event>(
+        db: &C,
+        event: E,
+    ) -> Result<event_outbox::Model, DbErr> {
+        let payload =
+            media_events::encode(PRODUCER, event).map_err(|e| DbErr::Custom(e.to_string()))?;
+        event_outbox::ActiveModel {
+            event_type: Set(E::TYPE.to_string()),
+            payload: Set(payload),
+            created_at: Set(Utc::now()),
+            sent_at: Set(None),
+            attempts: Set(0),
+            last_error: Set(None),
+            trace_context: Set(serde_json::to_value(media_telemetry::save_context()).ok()),
+            ..Default::default()
+        }
+        .insert(db)
+        .await
+    }
+
+    pub async fn mark_event_sent<C: ConnectionTrait>(db: &C, id: i32) -> Result<(), DbErr> {
+        EventOutbox::update_many()
+            .col_expr(event_outbox::Column::SentAt, Expr::value(Utc::now()))
+            .filter(event_outbox::Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn record_event_failure<C: ConnectionTrait>(
+        db: &C,
+        id: i32,
+        error: String,
+    ) -> Result<(), DbErr> {
+        EventOutbox::update_many()
+            .col_expr(
+                event_outbox::Column::Attempts,
+                Expr::col(event_outbox::Column::Attempts).add(1),
+            )
+            .col_expr(event_outbox::Column::LastError, Expr::value(error))
+            .filter(event_outbox::Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Stops relaying `id`, recording the error that made it give up.
+    pub async fn park_event<C: ConnectionTrait>(
+        db: &C,
+        id: i32,
+        error: String,
+    ) -> Result<(), DbErr> {
+        EventOutbox::update_many()
+            .col_expr(
+                event_outbox::Column::Attempts,
+                Expr::col(event_outbox::Column::Attempts).add(1),
+            )
+            .col_expr(event_outbox::Column::LastError, Expr::value(error))
+            .col_expr(event_outbox::Column::ParkedAt, Expr::value(Utc::now()))
+            .filter(event_outbox::Column::Id.eq(id))
+            .exec(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Records that `consumer` applied `event_id`. Returns `false` if it
+    /// already had, in which case the caller should skip the event; pass the
+    /// transaction that applies it so the record rolls back with it.
+    pub async fn record_processed_event<C: ConnectionTrait>(
+        db: &C,
+        consumer: &str,
+        event_id: Uuid,
+    ) -> Result<bool, DbErr> {
+        let inserted = ProcessedEvents::insert(processed_events::ActiveModel {
+            consumer: Set(consumer.to_string()),
+            event_id: Set(event_id),
+            processed_at: Set(Utc::now()),
+        })
+        .on_conflict(
+            OnConflict::columns([
+                processed_events::Column::Consumer,
+                processed_events::Column::EventId,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .exec_without_returning(db)
+        .await?;
+        Ok(inserted > 0)
+    }
+
+    /// Forgets events processed before `before`. Redeliveries don't arrive
+    /// that late, so the ledger doesn't need to keep them.
+    pub async fn delete_processed_events(
+        db: &DbConn,
+        before: DateTime<Utc>,
+    ) -> Result<DeleteResult, DbErr> {
+        ProcessedEvents::delete_many()
+            .filter(processed_events::Column::ProcessedAt.lt(before))
+            .exec(db)
+            .await
+    }
+
+    /// Drops events the broker confirmed before `before`.
+    pub async fn delete_sent_events(
+        db: &DbConn,
+        before: DateTime<Utc>,
+    ) -> Result<DeleteResult, DbErr> {
+        EventOutbox::delete_many()
+            .filter(event_outbox::Column::SentAt.lt(before))
+            .exec(db)
+            .await
+    }
 
     // pub async fn delete_all_posts(db: &DbConn) -> Result<DeleteResult, DbErr> {
     //     Post::delete_many().exec(db).await
@@ -1,33 +1,128 @@
-use crate::entity::{user_media, user_media::Entity as UserMedia};
-use sea_orm::*;
+use crate::entity::{
+    event_outbox, event_outbox::Entity as EventOutbox, media_renditions,
+    media_renditions::Entity as MediaRenditions, tus_upload, tus_upload::Entity as TusUpload,
+    user_media, user_media::Entity as UserMedia,
+};
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    sea_query::{LockBehavior, LockType},
+    *,
+};
 
 pub struct Query;
 
 impl Query {
-    pub async fn find_post_by_media_id(
-        db: &DbConn,
+    pub async fn find_post_by_media_id<C: ConnectionTrait>(
+        db: &C,
         media_id: &str,
     ) -> Result<Option<user_media::Model>, DbErr> {
         let user_media = UserMedia::find()
-            .filter(user_media::Column::MediaId.contains(media_id))
+            .filter(user_media::Column::MediaId.eq(media_id))
             .one(db)
             .await?;
         Ok(user_media)
     }
 
-    /// If ok, returns (post models, num pages).
-    pub async fn find_posts_in_page(
+    /// Returns up to `limit` of the user's media, newest first, starting after `before_id`.
+    pub async fn find_user_media_before(
+        db: &DbConn,
+        user_id: i32,
+        before_id: Option<i32>,
+        statuses: &[String],
+        limit: u64,
+    ) -> Result<Vec<user_media::Model>, DbErr> {
+        let mut query = UserMedia::find().filter(user_media::Column::UserId.eq(user_id));
+        if let Some(before_id) = before_id {
+            query = query.filter(user_media::Column::Id.lt(before_id));
+        }
+        if !statuses.is_empty() {
+            query = query.filter(user_media::Column::Status.is_in(statuses.iter().cloned()));
+        }
+        query
+            .order_by_desc(user_media::Column::Id)
+            .limit(limit)
+            .all(db)
+            .await
+    }
+
+    /// Returns the renditions of every listed media item, largest first.
+    pub async fn find_renditions_by_user_media_ids(
+        db: &DbConn,
+        user_media_ids: &[i32],
+    ) -> Result<Vec<media_renditions::Model>, DbErr> {
+        if user_media_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        MediaRenditions::find()
+            .filter(media_renditions::Column::UserMediaId.is_in(user_media_ids.iter().copied()))
+            .order_by_desc(media_renditions::Column::Width)
+            .all(db)
+            .await
+    }
+
+    /// Presigned uploads reserved before `before` that were never completed.
+    /// Rows backing a tus upload are left to the tus expiry.
+    pub async fn find_stale_pending_media(
+        db: &DbConn,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<user_media::Model>, DbErr> {
+        UserMedia::find()
+            .filter(user_media::Column::Status.eq("pending"))
+            .filter(user_media::Column::CreatedAt.lt(before))
+            .filter(
+                user_media::Column::MediaId.not_in_subquery(
+                    sea_query::Query::select()
+                        .column(tus_upload::Column::Id)
+                        .from(TusUpload)
+                        .to_owned(),
+                ),
+            )
+            .all(db)
+            .await
+    }
+
+    pub async fn find_tus_upload_by_id(
         db: &DbConn,
-        page: u64,
-        posts_per_page: u64,
-    ) -> Result<(Vec<user_media::Model>, u64), DbErr> {
-        // Setup paginator
-        let paginator = UserMedia::find()
-            .order_by_asc(user_media::Column::Id)
-            .paginate(db, posts_per_page);
-        let num_pages = paginator.num_pages().await?;
-
-        // Fetch paginated posts
-        paginator.fetch_page(page - 1).await.map(|p| (p, num_pages))
+        id: &str,
+    ) -> Result<Option<tus_upload::Model>, DbErr> {
+        TusUpload::find_by_id(id).one(db).await
+    }
+
+    /// The upload row, locked until `db` commits. `None` if it's gone. Only
+    /// held briefly, writers that stream a body claim the upload instead.
+    pub async fn lock_tus_upload<C: ConnectionTrait>(
+        db: &C,
+        id: &str,
+    ) -> Result<Option<tus_upload::Model>, DbErr> {
+        TusUpload::find_by_id(id)
+            .lock(LockType::Update)
+            .one(db)
+            .await
+    }
+
+    pub async fn find_expired_tus_uploads(
+        db: &DbConn,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<tus_upload::Model>, DbErr> {
+        TusUpload::find()
+            .filter(tus_upload::Column::ExpiresAt.lt(now))
+            .all(db)
+            .await
+    }
+
+    /// The oldest unsent outbox events, locked until `db` commits. Rows another
+    /// relay already holds are skipped, so replicas don't publish them twice.
+    pub async fn lock_pending_events<C: ConnectionTrait>(
+        db: &C,
+        limit: u64,
+    ) -> Result<Vec<event_outbox::Model>, DbErr> {
+        EventOutbox::find()
+            .filter(event_outbox::Column::SentAt.is_null())
+            .filter(event_outbox::Column::ParkedAt.is_null())
+            .order_by_asc(event_outbox::Column::Id)
+            .limit(limit)
+            .lock_with_behavior(LockType::Update, LockBehavior::SkipLocked)
+            .all(db)
+            .await
     }
 }
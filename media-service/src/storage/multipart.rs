@@ -0,0 +1,153 @@
+use aws_sdk_s3::{
+    operation::abort_multipart_upload::AbortMultipartUploadError,
+    primitives::ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart},
+};
+
+use crate::{
+    entity::tus_upload::{UploadedPart, UploadedParts},
+    handlers::handlers::{MediaError, MEDIA_BUCKET},
+};
+
+/// S3 rejects non-final parts smaller than 5 MiB.
+pub const PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Writes bytes into an S3 multipart upload while holding at most one part in memory.
+pub struct MultipartWriter {
+    s3_client: aws_sdk_s3::Client,
+    key: String,
+    upload_id: String,
+    pub parts: UploadedParts,
+    /// Bytes received since the last flushed part.
+    pub buffer: Vec<u8>,
+}
+
+impl MultipartWriter {
+    pub async fn create(
+        s3_client: &aws_sdk_s3::Client,
+        key: &str,
+        content_type: &str,
+    ) -> Result<Self, MediaError> {
+        let output = s3_client
+            .create_multipart_upload()
+            .bucket(MEDIA_BUCKET)
+            .key(key)
+            .content_type(content_type)
+            .send()
+            .await?;
+        let upload_id = output
+            .upload_id()
+            .ok_or_else(|| MediaError::Internal("S3 returned no upload id".to_string()))?;
+        Ok(Self::resume(
+            s3_client,
+            key,
+            upload_id,
+            UploadedParts::default(),
+            Vec::new(),
+        ))
+    }
+
+    /// Picks an upload back up from previously persisted parts and buffered bytes.
+    pub fn resume(
+        s3_client: &aws_sdk_s3::Client,
+        key: &str,
+        upload_id: &str,
+        parts: UploadedParts,
+        buffer: Vec<u8>,
+    ) -> Self {
+        Self {
+            s3_client: s3_client.clone(),
+            key: key.to_string(),
+            upload_id: upload_id.to_string(),
+            parts,
+            buffer,
+        }
+    }
+
+    pub fn upload_id(&self) -> &str {
+        &self.upload_id
+    }
+
+    pub async fn write(&mut self, bytes: &[u8]) -> Result<(), MediaError> {
+        self.buffer.extend_from_slice(bytes);
+        while self.buffer.len() >= PART_SIZE {
+            let rest = self.buffer.split_off(PART_SIZE);
+            let part = std::mem::replace(&mut self.buffer, rest);
+            self.upload_part(part).await?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the remaining bytes as the final part and assembles the object.
+    pub async fn finish(&mut self) -> Result<(), MediaError> {
+        if !self.buffer.is_empty() || self.parts.0.is_empty() {
+            let part = std::mem::take(&mut self.buffer);
+            self.upload_part(part).await?;
+        }
+        let completed = CompletedMultipartUpload::builder()
+            .set_parts(Some(
+                self.parts
+                    .0
+                    .iter()
+                    .map(|p| {
+                        CompletedPart::builder()
+                            .part_number(p.part_number)
+                            .e_tag(&p.e_tag)
+                            .build()
+                    })
+                    .collect(),
+            ))
+            .build();
+        self.s3_client
+            .complete_multipart_upload()
+            .bucket(MEDIA_BUCKET)
+            .key(&self.key)
+            .upload_id(&self.upload_id)
+            .multipart_upload(completed)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Drops the uploaded parts. An upload S3 no longer knows, because it
+    /// was completed or aborted before, counts as aborted.
+    pub async fn abort(self) -> Result<(), MediaError> {
+        match self
+            .s3_client
+            .abort_multipart_upload()
+            .bucket(MEDIA_BUCKET)
+            .key(&self.key)
+            .upload_id(&self.upload_id)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(AbortMultipartUploadError::is_no_such_upload) =>
+            {
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn upload_part(&mut self, bytes: Vec<u8>) -> Result<(), MediaError> {
+        let part_number = self.parts.0.len() as i32 + 1;
+        let output = self
+            .s3_client
+            .upload_part()
+            .bucket(MEDIA_BUCKET)
+            .key(&self.key)
+            .upload_id(&self.upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(bytes))
+            .send()
+            .await?;
+        self.parts.0.push(UploadedPart {
+            part_number,
+            e_tag: output.e_tag().unwrap_or_default().to_string(),
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+//! Tracing setup shared by the media services.
+//!
+//! Spans are exported over OTLP and their context travels with every AMQP
+//! message as W3C `traceparent` headers, so one upload shows up as one trace
+//! from the HTTP request through every service that handles its events.
+
+mod propagation;
+
+pub use propagation::{continue_from_headers, continue_from_saved, inject, save_context};
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+/// Keeps span export running. Shut it down before exiting so the last
+/// batch isn't lost.
+pub struct Telemetry {
+    provider: SdkTracerProvider,
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        if let Err(e) = self.provider.shutdown() {
+            tracing::error!("failed to flush spans: {}", e);
+        }
+    }
+}
+
+/// Logs to stdout, filtered by `RUST_LOG` and defaulting to `info`, and
+/// exports spans to the collector named by `OTEL_EXPORTER_OTLP_ENDPOINT`.
+pub fn init(service_name: &'static str) -> Result<Telemetry, String> {
+    let exporter = SpanExporter::builder()
+        .with_tonic()
+        .build()
+        .map_err(|e| format!("failed to build span exporter: {}", e))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name).build())
+        .build();
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer = provider.tracer(service_name);
+    tracing_subscriber::registry()
+        .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()
+        .map_err(|e| format!("failed to install tracing subscriber: {}", e))?;
+    Ok(Telemetry { provider })
+}
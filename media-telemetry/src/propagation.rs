@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+use amqprs::{FieldTable, FieldValue};
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    Context,
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Writes the current span's trace context into AMQP message headers.
+pub fn inject(headers: &mut FieldTable) {
+    let cx = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&cx, &mut HeaderInjector(headers))
+    });
+}
+
+/// Makes `span` part of the trace a publisher left in `headers`. Messages
+/// without one leave the span where it is, usually at the root of a new trace.
+pub fn continue_from_headers(span: &tracing::Span, headers: Option<&FieldTable>) {
+    let Some(headers) = headers else {
+        return;
+    };
+    let cx =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    set_parent(span, cx);
+}
+
+/// The current span's trace context as plain key/value pairs, for work that
+/// is picked up later, outside the span.
+pub fn save_context() -> HashMap<String, String> {
+    let cx = tracing::Span::current().context();
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&cx, &mut carrier));
+    carrier
+}
+
+/// Makes `span` part of a trace saved with `save_context`.
+pub fn continue_from_saved(span: &tracing::Span, saved: &HashMap<String, String>) {
+    let cx = global::get_text_map_propagator(|propagator| propagator.extract(saved));
+    set_parent(span, cx);
+}
+
+fn set_parent(span: &tracing::Span, cx: Context) {
+    // only fails once the span has been entered, which callers do afterwards
+    let _ = span.set_parent(cx);
+}
+
+struct HeaderInjector<'a>(&'a mut FieldTable);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let Ok(key) = key.try_into() {
+            self.0.insert(key, FieldValue::from(value));
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a FieldTable);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        match self.0.get(&key.try_into().ok()?)? {
+            FieldValue::S(value) => Some(value.as_ref().as_str()),
+            _ => None,
+        }
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .as_ref()
+            .keys()
+            .map(|key| key.as_ref().as_str())
+            .collect()
+    }
+}